@@ -1,5 +1,9 @@
 #![feature(test)]
+#![cfg_attr(target_family = "postgres", feature(postgres_ext, custom_test_frameworks))]
+#![cfg_attr(target_family = "postgres", test_runner(postgres::run))]
 
 extern crate test;
 
 mod hash;
+#[cfg(target_family = "postgres")]
+mod postgres;
@@ -0,0 +1,68 @@
+//! What `set_scrub` costs. Each benchmark runs once with scrubbing off and
+//! once with it on, so the two can be compared.
+
+use std::hint::black_box;
+use std::os::postgres::alloc::set_scrub;
+use test::Bencher;
+
+fn with_scrub(fill: Option<u8>, b: &mut Bencher, f: impl FnMut()) {
+    unsafe { set_scrub(fill) };
+    b.iter(f);
+    unsafe { set_scrub(None) };
+}
+
+// Allocates and frees a block of `size` bytes.
+fn alloc_free(size: usize) -> impl FnMut() {
+    move || drop(black_box(Vec::<u8>::with_capacity(size)))
+}
+
+// Builds a string the way formatting code does, growing it from empty.
+fn grow() -> impl FnMut() {
+    || {
+        let mut s = String::new();
+        for i in 0..1000 {
+            s.push_str(black_box(if i % 2 == 0 { "abcdefgh" } else { "ijklmnop" }));
+        }
+        black_box(s);
+    }
+}
+
+#[bench]
+fn alloc_free_64(b: &mut Bencher) {
+    with_scrub(None, b, alloc_free(64))
+}
+
+#[bench]
+fn alloc_free_64_scrubbed(b: &mut Bencher) {
+    with_scrub(Some(0), b, alloc_free(64))
+}
+
+#[bench]
+fn alloc_free_4k(b: &mut Bencher) {
+    with_scrub(None, b, alloc_free(4096))
+}
+
+#[bench]
+fn alloc_free_4k_scrubbed(b: &mut Bencher) {
+    with_scrub(Some(0), b, alloc_free(4096))
+}
+
+#[bench]
+fn alloc_free_1m(b: &mut Bencher) {
+    with_scrub(None, b, alloc_free(1 << 20))
+}
+
+#[bench]
+fn alloc_free_1m_scrubbed(b: &mut Bencher) {
+    with_scrub(Some(0), b, alloc_free(1 << 20))
+}
+
+#[bench]
+fn grow_string(b: &mut Bencher) {
+    with_scrub(None, b, grow())
+}
+
+#[bench]
+fn grow_string_scrubbed(b: &mut Bencher) {
+    with_scrub(Some(0), b, grow())
+}
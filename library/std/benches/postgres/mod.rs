@@ -0,0 +1,42 @@
+//! Benchmarks of the postgres-specific parts of std.
+//!
+//! libtest's console runner needs threads and a stdout that reaches the
+//! terminal, neither of which the postgres target has, so on that target
+//! these benches, and the rest, run through `run` instead. It runs each one
+//! with `test::bench::benchmark` and writes the results straight to file
+//! descriptor 1. Built against a sysroot of the target:
+//!
+//! ```text
+//! rustc --edition 2021 --test -O --target x86_64-postgres-linux-gnu.json \
+//!     --sysroot <sysroot> library/std/benches/lib.rs -o stdbenches
+//! ```
+
+use test::test::{TestId, TestResult};
+use test::{StaticBenchFn, TestDescAndFn};
+
+mod alloc;
+
+extern "C" {
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+}
+
+pub fn run(tests: &[&TestDescAndFn]) {
+    let (tx, rx) = std::sync::mpsc::channel();
+    for (i, test) in tests.iter().enumerate() {
+        let StaticBenchFn(f) = test.testfn else { continue };
+        test::bench::benchmark(TestId(i), test.desc.clone(), tx.clone(), true, f);
+        let result = match rx.recv().map(|completed| completed.result) {
+            Ok(TestResult::TrBench(samples)) => test::bench::fmt_bench_samples(&samples),
+            Ok(result) => format!("{result:?}"),
+            Err(_) => String::from("no result"),
+        };
+        let line = format!("{:<40} {}\n", test.desc.name.as_slice(), result);
+        let mut rest = line.as_bytes();
+        while !rest.is_empty() {
+            match unsafe { write(1, rest.as_ptr(), rest.len()) } {
+                n if n > 0 => rest = &rest[n as usize..],
+                _ => break,
+            }
+        }
+    }
+}
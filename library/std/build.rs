@@ -1,4 +1,6 @@
 use std::env;
+use std::fs;
+use std::path::Path;
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
@@ -56,4 +58,159 @@ fn main() {
     }
     println!("cargo:rustc-env=STD_ENV_ARCH={}", env::var("CARGO_CFG_TARGET_ARCH").unwrap());
     println!("cargo:rustc-cfg=backtrace_in_libstd");
+    if env::var("CARGO_CFG_TARGET_FAMILY").unwrap().split(',').any(|f| f == "postgres") {
+        check_postgres_panics();
+    }
+}
+
+/// Rejects the macros and methods that panic in `sys/postgres`: `panic!`,
+/// `unreachable!`, `unimplemented!`, `todo!`, the `assert!` family (but not
+/// `debug_assert!`), `.unwrap()` and `.expect(..)`.
+///
+/// A panic there turns an ordinary library call into a transaction abort,
+/// so every entry point has a defined result instead: an `Err`, an empty
+/// iterator, an opaque value. Comments and literals are blanked out before
+/// looking, and `tests.rs` files are skipped. A line may still panic if a
+/// comment right above it starts with `Panics on purpose:` and says why.
+fn check_postgres_panics() {
+    const PANICKING: [&str; 9] = [
+        "panic!",
+        "unreachable!",
+        "unimplemented!",
+        "todo!",
+        "assert!",
+        "assert_eq!",
+        "assert_ne!",
+        ".unwrap()",
+        ".expect(",
+    ];
+
+    fn is_ident(c: u8) -> bool {
+        c == b'_' || c.is_ascii_alphanumeric()
+    }
+
+    // Replaces comments, string literals and char literals with spaces,
+    // keeping newlines so that line numbers stay the same.
+    fn blank(source: &str) -> String {
+        let src = source.as_bytes();
+        let mut out = src.to_vec();
+        let mut i = 0;
+        while i < src.len() {
+            let start = i;
+            match src[i] {
+                b'/' if src.get(i + 1) == Some(&b'/') => {
+                    while i < src.len() && src[i] != b'\n' {
+                        i += 1;
+                    }
+                }
+                b'/' if src.get(i + 1) == Some(&b'*') => {
+                    let mut depth = 0;
+                    while i < src.len() {
+                        if src[i..].starts_with(b"/*") {
+                            depth += 1;
+                            i += 2;
+                        } else if src[i..].starts_with(b"*/") {
+                            depth -= 1;
+                            i += 2;
+                            if depth == 0 {
+                                break;
+                            }
+                        } else {
+                            i += 1;
+                        }
+                    }
+                }
+                b'r' if (i == 0 || !is_ident(src[i - 1]))
+                    && matches!(src.get(i + 1), Some(b'"' | b'#')) =>
+                {
+                    let hashes = src[i + 1..].iter().take_while(|&&b| b == b'#').count();
+                    if src.get(i + 1 + hashes) != Some(&b'"') {
+                        i += 1;
+                        continue;
+                    }
+                    let mut close = vec![b'"'];
+                    close.extend(std::iter::repeat(b'#').take(hashes));
+                    i += 2 + hashes;
+                    while i < src.len() && !src[i..].starts_with(&close) {
+                        i += 1;
+                    }
+                    i = (i + close.len()).min(src.len());
+                }
+                b'"' => {
+                    i += 1;
+                    while i < src.len() && src[i] != b'"' {
+                        i += if src[i] == b'\\' { 2 } else { 1 };
+                    }
+                    i = (i + 1).min(src.len());
+                }
+                // A char literal, as opposed to a lifetime or a label.
+                b'\'' if src.get(i + 1) == Some(&b'\\') || src.get(i + 2) == Some(&b'\'') => {
+                    i += 1;
+                    while i < src.len() && src[i] != b'\'' {
+                        i += if src[i] == b'\\' { 2 } else { 1 };
+                    }
+                    i = (i + 1).min(src.len());
+                }
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+            for b in &mut out[start..i.min(src.len())] {
+                if *b != b'\n' {
+                    *b = b' ';
+                }
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    fn panics(code: &str) -> bool {
+        PANICKING.iter().any(|m| {
+            code.match_indices(m)
+                .any(|(at, _)| m.starts_with('.') || at == 0 || !is_ident(code.as_bytes()[at - 1]))
+        })
+    }
+
+    // Whether the comment right above line `i` says the panic is deliberate.
+    fn exempt(lines: &[&str], i: usize) -> bool {
+        lines[..i]
+            .iter()
+            .rev()
+            .map(|line| line.trim_start())
+            .take_while(|line| line.starts_with("//"))
+            .any(|line| line.starts_with("// Panics on purpose:"))
+    }
+
+    fn visit(dir: &Path, found: &mut Vec<String>) {
+        let mut entries: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                visit(&path, found);
+            } else if path.extension().map_or(false, |e| e == "rs")
+                && path.file_name().map_or(false, |n| n != "tests.rs")
+            {
+                let source = fs::read_to_string(&path).unwrap();
+                let lines: Vec<_> = source.lines().collect();
+                let blanked = blank(&source);
+                for (i, code) in blanked.lines().enumerate() {
+                    if panics(code) && !exempt(&lines, i) {
+                        found.push(format!("{}:{}: {}", path.display(), i + 1, lines[i].trim()));
+                    }
+                }
+            }
+        }
+    }
+
+    let dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/sys/postgres");
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let mut found = Vec::new();
+    visit(&dir, &mut found);
+    if !found.is_empty() {
+        panic!(
+            "sys/postgres must not panic, return a graceful result instead:\n{}",
+            found.join("\n")
+        );
+    }
 }
@@ -331,6 +331,12 @@ pub fn take_alloc_error_hook() -> fn(Layout) {
     if hook.is_null() { default_alloc_error_hook } else { unsafe { mem::transmute(hook) } }
 }
 
+// On postgres, `rust_oom` unwinds once the hook returns, which ends the call
+// with an error, and there is no stderr to print to.
+#[cfg(target_family = "postgres")]
+fn default_alloc_error_hook(_layout: Layout) {}
+
+#[cfg(not(target_family = "postgres"))]
 fn default_alloc_error_hook(layout: Layout) {
     extern "Rust" {
         // This symbol is emitted by rustc next to __rust_alloc_error_handler.
@@ -351,10 +357,17 @@ fn default_alloc_error_hook(layout: Layout) {
 #[alloc_error_handler]
 #[unstable(feature = "alloc_internals", issue = "none")]
 pub fn rust_oom(layout: Layout) -> ! {
+    // On postgres, the allocator also fails once an interrupt is pending,
+    // which is no reason to run the hook.
+    #[cfg(target_family = "postgres")]
+    crate::sys::interrupt::check_now();
     let hook = HOOK.load(Ordering::SeqCst);
     let hook: fn(Layout) =
         if hook.is_null() { default_alloc_error_hook } else { unsafe { mem::transmute(hook) } };
     hook(layout);
+    #[cfg(target_family = "postgres")]
+    crate::sys::alloc::out_of_memory(layout);
+    #[cfg(not(target_family = "postgres"))]
     crate::process::abort()
 }
 
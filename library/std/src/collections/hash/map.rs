@@ -3120,6 +3120,13 @@ impl RandomState {
         // iteration order allows a form of DOS attack. To counter that we
         // increment one of the seeds on every RandomState creation, giving
         // every corresponding HashMap a different iteration order.
+        //
+        // On postgres, a function declared immutable gets the same keys for
+        // every map instead, so that it iterates them the same way each time.
+        #[cfg(target_family = "postgres")]
+        if let Some((k0, k1)) = sys::fixed_hashmap_keys() {
+            return RandomState { k0, k1 };
+        }
         thread_local!(static KEYS: Cell<(u64, u64)> = {
             Cell::new(sys::hashmap_random_keys())
         });
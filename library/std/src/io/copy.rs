@@ -115,6 +115,9 @@ where
         let mut len = 0;
 
         loop {
+            #[cfg(target_family = "postgres")]
+            crate::sys::interrupt::check();
+
             // Hack: this relies on `impl Read for BufReader` always calling fill_buf
             // if the buffer is empty, even for empty slices.
             // It can't be called directly here since specialization prevents us
@@ -174,6 +177,9 @@ impl<I: Write + ?Sized> BufferedWriterSpec for BufWriter<I> {
         let mut init = 0;
 
         loop {
+            #[cfg(target_family = "postgres")]
+            crate::sys::interrupt::check();
+
             let buf = self.buffer_mut();
             let mut read_buf: BorrowedBuf<'_> = buf.spare_capacity_mut().into();
 
@@ -222,6 +228,9 @@ fn stack_buffer_copy<R: Read + ?Sized, W: Write + ?Sized>(
     let mut len = 0;
 
     loop {
+        #[cfg(target_family = "postgres")]
+        crate::sys::interrupt::check();
+
         match reader.read_buf(buf.unfilled()) {
             Ok(()) => {}
             Err(e) if e.kind() == ErrorKind::Interrupted => continue,
@@ -1028,7 +1028,10 @@ fn print_to_buffer_if_capture_used(args: fmt::Arguments<'_>) -> bool {
             // our printing recursively panics/prints, so the recursive
             // panic/print goes to the global sink instead of our local sink.
             s.take().map(|w| {
+                #[cfg(not(target_family = "postgres"))]
                 let _ = w.lock().unwrap_or_else(|e| e.into_inner()).write_fmt(args);
+                #[cfg(target_family = "postgres")]
+                stdio::write_capture(&mut w.lock().unwrap_or_else(|e| e.into_inner()), args);
                 s.set(Some(w));
             })
         }) == Ok(Some(()))
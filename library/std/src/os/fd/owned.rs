@@ -274,6 +274,9 @@ impl AsFd for OwnedFd {
     }
 }
 
+// On postgres, a file may be a Postgres temporary file or a mounted one, which
+// has no descriptor that could be handed out.
+#[cfg(not(target_family = "postgres"))]
 #[stable(feature = "io_safety", since = "1.63.0")]
 impl AsFd for fs::File {
     #[inline]
@@ -282,6 +285,7 @@ impl AsFd for fs::File {
     }
 }
 
+#[cfg(not(target_family = "postgres"))]
 #[stable(feature = "io_safety", since = "1.63.0")]
 impl From<fs::File> for OwnedFd {
     #[inline]
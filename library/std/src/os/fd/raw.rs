@@ -14,6 +14,7 @@ use crate::os::unix::io::AsFd;
 use crate::os::unix::io::OwnedFd;
 #[cfg(target_os = "wasi")]
 use crate::os::wasi::io::OwnedFd;
+#[cfg(not(target_family = "postgres"))]
 use crate::sys_common::{AsInner, IntoInner};
 #[cfg(target_os = "hermit")]
 use hermit_abi as libc;
@@ -161,6 +162,8 @@ impl FromRawFd for RawFd {
     }
 }
 
+// See `AsFd for fs::File`.
+#[cfg(not(target_family = "postgres"))]
 #[stable(feature = "rust1", since = "1.0.0")]
 impl AsRawFd for fs::File {
     #[inline]
@@ -175,6 +178,7 @@ impl FromRawFd for fs::File {
         unsafe { fs::File::from(OwnedFd::from_raw_fd(fd)) }
     }
 }
+#[cfg(not(target_family = "postgres"))]
 #[stable(feature = "into_raw_os", since = "1.4.0")]
 impl IntoRawFd for fs::File {
     #[inline]
@@ -1,4 +1,5 @@
-#[cfg(any(unix, target_os = "wasi"))]
+// Files have no descriptor to give out on postgres.
+#[cfg(all(any(unix, target_os = "wasi"), not(target_family = "postgres")))]
 #[test]
 fn test_raw_fd() {
     #[cfg(unix)]
@@ -14,7 +15,7 @@ fn test_raw_fd() {
     assert_eq!(stdin_as_file.into_raw_fd(), 0);
 }
 
-#[cfg(any(unix, target_os = "wasi"))]
+#[cfg(all(any(unix, target_os = "wasi"), not(target_family = "postgres")))]
 #[test]
 fn test_fd() {
     #[cfg(unix)]
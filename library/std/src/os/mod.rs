@@ -131,6 +131,8 @@ pub mod netbsd;
 pub mod nto;
 #[cfg(target_os = "openbsd")]
 pub mod openbsd;
+#[cfg(target_family = "postgres")]
+pub mod postgres;
 #[cfg(target_os = "redox")]
 pub mod redox;
 #[cfg(target_os = "solaris")]
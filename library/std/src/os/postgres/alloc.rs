@@ -0,0 +1,277 @@
+//! Postgres-specific memory allocation functionality.
+//!
+//! These configure the allocator behind [`System`], which the Rust code in a
+//! backend allocates from. The host sets them up at the start of each
+//! function call, so that each call gets the settings of the function being
+//! called.
+//!
+//! [`System`]: crate::alloc::System
+
+use crate::ffi::CStr;
+use crate::fmt;
+use crate::sys;
+
+/// Overwrites memory with `fill` as it is freed, or stops doing so if `fill`
+/// is `None`.
+///
+/// Functions of different roles share the backend's heap, so without this a
+/// block one of them frees can show up, with its old contents, in an
+/// allocation another one makes, where a careless `MaybeUninit` in some
+/// dependency may read it. With it, freed blocks are overwritten in full,
+/// and [`realloc`] always moves the block and overwrites the old one, since
+/// growing or shrinking in place would hand back memory unseen.
+///
+/// The cost is a `memset` of every freed block and a copy on every
+/// `realloc`, so freeing takes time in proportion to the size of the block.
+/// Code that mostly reuses its allocations hardly notices; code that churns
+/// through large buffers does.
+///
+/// With the `postgres::alloc` benches in `library/std/benches`, run on one
+/// core of a Xeon VM, allocating and freeing a block took some 20 ns longer
+/// at 64 bytes, 50 ns longer at 4 KiB, and 30 µs longer at 1 MiB, against
+/// under 60 ns without scrubbing. Growing a `String` to 8 KB a few bytes at
+/// a time, a dozen `realloc`s, took about 30 µs either way; the difference
+/// was lost in the noise.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+///
+/// [`realloc`]: crate::alloc::GlobalAlloc::realloc
+pub unsafe fn set_scrub(fill: Option<u8>) {
+    sys::alloc::set_scrub(fill)
+}
+
+/// Limits how much memory the current call may allocate, net of what it
+/// frees, to `budget` bytes, or lifts the limit if `budget` is `None`.
+///
+/// The host calls this at the start of every call, with a budget derived
+/// from `work_mem` or a setting of its own. Counting starts from zero each
+/// time, and memory allocated before then is not counted. Freeing it is,
+/// though, down to zero: a call that frees what an earlier one left behind
+/// can allocate that much more, but only once it has allocated as much.
+///
+/// An allocation that would go over the budget fails: the allocator returns
+/// null, as if the system were out of memory. Fallible APIs such as
+/// [`Vec::try_reserve`] report that as an error. Everything else goes to the
+/// [alloc error hook](crate::alloc::set_alloc_error_hook), after which the
+/// call unwinds with an [`OutOfMemory`] payload, whether or not it was built
+/// with `-Zoom=panic`, and without running the panic hook. The host catches
+/// it at the function boundary and reports that the backend is out of
+/// memory. Either way the backend stops short of growing until the operating
+/// system steps in.
+///
+/// Once that has happened, the budget is lifted for the rest of the call, so
+/// that unwinding, which allocates too, can't fail the same way.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_budget(budget: Option<usize>) {
+    sys::alloc::set_budget(budget)
+}
+
+/// The payload a call unwinds with when an allocation fails, whether it went
+/// over the [budget](set_budget) or the system is out of memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfMemory {
+    size: usize,
+}
+
+impl OutOfMemory {
+    pub(crate) fn new(size: usize) -> OutOfMemory {
+        OutOfMemory { size }
+    }
+
+    /// Returns the size of the allocation that failed, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "memory allocation of {} bytes failed", self.size)
+    }
+}
+
+/// Returns the allocator's counters.
+///
+/// The number of bytes allocated right now covers the whole backend; the
+/// other counters cover the time since [`reset_stats`] was last called,
+/// which the host usually does at the start of each call.
+pub fn stats() -> Stats {
+    let stats = sys::alloc::stats();
+    Stats {
+        current_bytes: stats.live_bytes,
+        peak_bytes: stats.peak_bytes,
+        allocations: stats.allocations,
+        reallocations: stats.reallocations,
+        largest_request: stats.largest,
+    }
+}
+
+/// Starts the counters [`stats`] returns over: the peak is set to what is
+/// allocated right now, and the rest to zero.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn reset_stats() {
+    sys::alloc::reset_stats()
+}
+
+/// The allocator's counters, as returned by [`stats`].
+///
+/// Sizes are the ones asked for, not what `malloc(3)` rounds them up to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    current_bytes: usize,
+    peak_bytes: usize,
+    allocations: u64,
+    reallocations: u64,
+    largest_request: usize,
+}
+
+impl Stats {
+    /// Returns the number of bytes allocated and not yet freed.
+    pub fn current_bytes(&self) -> usize {
+        self.current_bytes
+    }
+
+    /// Returns the most bytes that were allocated at once.
+    pub fn peak_bytes(&self) -> usize {
+        self.peak_bytes
+    }
+
+    /// Returns the number of allocations made.
+    pub fn allocations(&self) -> u64 {
+        self.allocations
+    }
+
+    /// Returns the number of times an allocation was grown or shrunk, which
+    /// isn't counted among [`allocations`](Stats::allocations) even when
+    /// the block has to move.
+    pub fn reallocations(&self) -> u64 {
+        self.reallocations
+    }
+
+    /// Returns the size of the largest allocation or reallocation, in
+    /// bytes.
+    pub fn largest_request(&self) -> usize {
+        self.largest_request
+    }
+}
+
+/// Turns the debug mode on or off.
+///
+/// In debug mode every allocation is recorded, with its size and the return
+/// addresses of the code that made it, until it is freed, and freed memory
+/// is overwritten with `0x7f`, as Postgres does with `CLOBBER_FREED_MEMORY`,
+/// unless [`set_scrub`] picked another byte. Under AddressSanitizer or
+/// Valgrind's Memcheck, freed memory is also poisoned with their client
+/// requests, so that a use after free is reported where it happens; that
+/// includes the old block of every `realloc`, which always moves in this
+/// mode. At the end of each call, the host collects what is still live with
+/// [`take_leaks`].
+///
+/// Allocations made while the mode is off are never reported, even if they
+/// are freed while it is on.
+///
+/// This is meant for finding leaks, not for production: every allocation
+/// unwinds the stack, and every free does a lookup.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_debug(enabled: bool) {
+    sys::alloc::set_debug(enabled)
+}
+
+/// Returns the allocations made since the last call to this function that
+/// are still live, and forgets them, so that each is only reported once.
+///
+/// The host calls this when the call's memory context is reset. Whatever
+/// Rust allocated during the call and didn't free by then is almost always
+/// a leak; the exceptions are caches meant to outlive the call, such as a
+/// `OnceLock` filled on first use.
+///
+/// The backend may also run under a leak checker of its own: LeakSanitizer,
+/// including AddressSanitizer's, or Valgrind's Memcheck. If so, this asks it
+/// to check for leaks too, and it reports what it finds itself. Those tools
+/// already poison freed memory, whatever is done here.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn take_leaks() -> Vec<Leak> {
+    let mut leaks = Vec::new();
+    sys::alloc::take_leaks(|size, align, frames| {
+        leaks.push(Leak { size, align, frames: frames.to_vec() })
+    });
+    sys::alloc::check_leaks();
+    leaks
+}
+
+/// An allocation that was still live at the end of a call, as returned by
+/// [`take_leaks`].
+///
+/// Its `Display` is a report for the log: the size, then a line for each
+/// frame of the allocation site, with the symbol and object it is in when
+/// those can be found.
+#[derive(Clone, Debug)]
+pub struct Leak {
+    size: usize,
+    align: usize,
+    frames: Vec<usize>,
+}
+
+impl Leak {
+    /// Returns the size of the allocation, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the alignment the allocation was made with.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// Returns the return addresses of the allocation site, innermost first,
+    /// starting inside the allocator.
+    ///
+    /// Backtraces are not symbolized on postgres targets, and this holds at
+    /// most a few frames. It is empty if the stack could not be unwound.
+    pub fn frames(&self) -> &[usize] {
+        &self.frames
+    }
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes leaked, allocated at", self.size)?;
+        if self.frames.is_empty() {
+            return f.write_str(" an unknown site");
+        }
+        for &ip in &self.frames {
+            write!(f, "\n    {ip:#x}")?;
+            let mut info: libc::Dl_info = unsafe { crate::mem::zeroed() };
+            // A return address may be just past the end of its call's
+            // function, so look up the byte before it.
+            if unsafe { libc::dladdr(crate::ptr::invalid(ip - 1), &mut info) } == 0 {
+                continue;
+            }
+            if !info.dli_sname.is_null() {
+                let name = unsafe { CStr::from_ptr(info.dli_sname) };
+                let offset = ip - info.dli_saddr as usize;
+                write!(f, " {}+{offset:#x}", name.to_string_lossy())?;
+            }
+            if !info.dli_fname.is_null() {
+                let object = unsafe { CStr::from_ptr(info.dli_fname) };
+                let offset = ip - info.dli_fbase as usize;
+                write!(f, " ({}+{offset:#x})", object.to_string_lossy())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+//! Postgres-specific environment functionality.
+//!
+//! The process environment of a backend is never visible on postgres targets.
+//! Instead, [`std::env::var`] and [`std::env::vars`] read a virtual
+//! environment the host provides, either as a fixed set of variables with
+//! [`set_vars`] or through an [`Environment`] with [`set_environment`]. It
+//! starts out empty, and it can't be changed from inside: [`std::env::set_var`]
+//! and [`std::env::remove_var`] still fail.
+//!
+//! [`std::env::var`]: crate::env::var
+//! [`std::env::vars`]: crate::env::vars
+//! [`std::env::set_var`]: crate::env::set_var
+//! [`std::env::remove_var`]: crate::env::remove_var
+
+use crate::collections::BTreeMap;
+use crate::ffi::{OsStr, OsString};
+use crate::sys;
+
+/// A source of environment variables.
+///
+/// A host that takes variables from a namespace of custom GUCs, such as
+/// `plrust.env.*`, can look one up with `GetConfigOption("plrust.env.<key>",
+/// true, false)` and find them all among `get_guc_variables()`, so that
+/// `SET` and `ALTER ROLE ... SET` apply as usual.
+pub trait Environment: Sync {
+    /// Returns the value of the variable `key`, if there is one.
+    fn get(&self, key: &OsStr) -> Option<OsString>;
+
+    /// Returns all variables and their values.
+    fn vars(&self) -> Vec<(OsString, OsString)>;
+}
+
+/// Replaces the environment with `env`.
+///
+/// # Safety
+///
+/// This decides what code running in the backend sees as its environment. It
+/// must only be called by the host.
+pub unsafe fn set_environment(env: &'static dyn Environment) {
+    sys::os::set_environment(sys::os::Environment::Host(env))
+}
+
+/// Replaces the environment with the variables in `vars`.
+///
+/// # Safety
+///
+/// This decides what code running in the backend sees as its environment. It
+/// must only be called by the host.
+pub unsafe fn set_vars(vars: BTreeMap<OsString, OsString>) {
+    sys::os::set_environment(sys::os::Environment::Vars(vars))
+}
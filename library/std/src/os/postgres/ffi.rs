@@ -0,0 +1,201 @@
+//! Postgres-specific extensions to primitives in the [`std::ffi`] module.
+//!
+//! [`OsStr`] and [`CStr`] are plain bytes on postgres targets, as on unix, but
+//! the bytes Postgres hands out are in the server encoding, which need not be
+//! UTF-8: it may be `LATIN1`, `EUC_JP` or `SQL_ASCII`, among others. The
+//! traits here convert between those bytes and Rust strings with Postgres's
+//! own conversions, once the host installs them with [`set_encoding`].
+//! Anything that doesn't convert cleanly is an [`EncodingError`].
+//!
+//! [`std::ffi`]: crate::ffi
+
+use crate::error::Error;
+use crate::ffi::{CStr, CString, OsStr, OsString};
+use crate::fmt;
+use crate::os::unix::ffi::{OsStrExt as _, OsStringExt as _};
+use crate::sealed::Sealed;
+use crate::sync::{PoisonError, RwLock};
+
+/// Postgres's encoding conversions.
+///
+/// Implementations report failures as an [`EncodingError`] instead of
+/// raising them. The length of the part that converts cleanly can be found
+/// with `pg_encoding_verifymbstr`.
+pub trait Encoding: Sync {
+    /// Converts UTF-8 `s` to the server encoding, like
+    /// `pg_any_to_server(s, len, PG_UTF8)`.
+    fn to_server(&self, s: &str) -> Result<Vec<u8>, EncodingError>;
+
+    /// Converts `bytes` from the server encoding to UTF-8, like
+    /// `pg_server_to_any(bytes, len, PG_UTF8)`.
+    ///
+    /// The result is checked again before it becomes a `String`, so that
+    /// `SQL_ASCII`, which converts nothing, can't let invalid UTF-8 through.
+    fn from_server(&self, bytes: &[u8]) -> Result<Vec<u8>, EncodingError>;
+}
+
+static ENCODING: RwLock<Option<&'static dyn Encoding>> = RwLock::new(None);
+
+/// Installs the implementation of [`Encoding`] the conversions here use.
+///
+/// Until this is called, every conversion fails with
+/// [`EncodingErrorKind::Unavailable`].
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_encoding(encoding: &'static dyn Encoding) {
+    *ENCODING.write().unwrap_or_else(PoisonError::into_inner) = Some(encoding);
+}
+
+fn encoding() -> Result<&'static dyn Encoding, EncodingError> {
+    ENCODING
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .ok_or(EncodingError::new(EncodingErrorKind::Unavailable, 0))
+}
+
+/// Converts `s` to bytes in the server encoding.
+pub fn to_server(s: &str) -> Result<Vec<u8>, EncodingError> {
+    encoding()?.to_server(s)
+}
+
+/// Converts `bytes` in the server encoding to a `String`.
+pub fn from_server(bytes: &[u8]) -> Result<String, EncodingError> {
+    let utf8 = encoding()?.from_server(bytes)?;
+    String::from_utf8(utf8).map_err(|e| {
+        EncodingError::new(EncodingErrorKind::InvalidSequence, e.utf8_error().valid_up_to())
+    })
+}
+
+/// The ways converting between encodings can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EncodingErrorKind {
+    /// The input is not valid in its encoding.
+    InvalidSequence,
+    /// A character has no equivalent in the target encoding.
+    Untranslatable,
+    /// The converted string has a nul byte, which a [`CString`] can't hold.
+    InteriorNul,
+    /// There is no conversion to use, because the host hasn't installed one
+    /// or Postgres has none between the two encodings.
+    Unavailable,
+}
+
+/// An error converting between the server encoding and UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodingError {
+    kind: EncodingErrorKind,
+    valid_up_to: usize,
+}
+
+impl EncodingError {
+    /// Creates an error of the given kind, where the first `valid_up_to`
+    /// bytes of the input converted cleanly.
+    pub fn new(kind: EncodingErrorKind, valid_up_to: usize) -> EncodingError {
+        EncodingError { kind, valid_up_to }
+    }
+
+    /// Returns what went wrong.
+    pub fn kind(&self) -> EncodingErrorKind {
+        self.kind
+    }
+
+    /// Returns how many bytes of the input converted cleanly before the
+    /// error.
+    ///
+    /// For [`InteriorNul`](EncodingErrorKind::InteriorNul) this is the
+    /// position of the nul byte in the output instead.
+    pub fn valid_up_to(&self) -> usize {
+        self.valid_up_to
+    }
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            EncodingErrorKind::InvalidSequence => "invalid byte sequence",
+            EncodingErrorKind::Untranslatable => {
+                "character has no equivalent in the target encoding"
+            }
+            EncodingErrorKind::InteriorNul => "nul byte in the converted string",
+            EncodingErrorKind::Unavailable => {
+                return f.write_str("no encoding conversion available")
+            }
+        };
+        write!(f, "{what} after {} valid bytes", self.valid_up_to)
+    }
+}
+
+impl Error for EncodingError {}
+
+/// Postgres-specific extensions to [`OsStr`].
+///
+/// This trait is sealed: it cannot be implemented outside the standard library.
+/// This is so that future additional methods are not breaking changes.
+pub trait OsStrExt: Sealed {
+    /// Converts these bytes from the server encoding to a `String`.
+    fn server_to_string(&self) -> Result<String, EncodingError>;
+}
+
+impl OsStrExt for OsStr {
+    fn server_to_string(&self) -> Result<String, EncodingError> {
+        from_server(self.as_bytes())
+    }
+}
+
+/// Postgres-specific extensions to [`OsString`].
+///
+/// This trait is sealed: it cannot be implemented outside the standard library.
+/// This is so that future additional methods are not breaking changes.
+pub trait OsStringExt: Sealed {
+    /// Converts `s` to an `OsString` in the server encoding.
+    fn from_str_to_server(s: &str) -> Result<Self, EncodingError>
+    where
+        Self: Sized;
+}
+
+impl OsStringExt for OsString {
+    fn from_str_to_server(s: &str) -> Result<OsString, EncodingError> {
+        to_server(s).map(OsString::from_vec)
+    }
+}
+
+impl Sealed for CStr {}
+
+/// Postgres-specific extensions to [`CStr`].
+///
+/// This trait is sealed: it cannot be implemented outside the standard library.
+/// This is so that future additional methods are not breaking changes.
+pub trait CStrExt: Sealed {
+    /// Converts this string from the server encoding to a `String`.
+    fn server_to_string(&self) -> Result<String, EncodingError>;
+}
+
+impl CStrExt for CStr {
+    fn server_to_string(&self) -> Result<String, EncodingError> {
+        from_server(self.to_bytes())
+    }
+}
+
+impl Sealed for CString {}
+
+/// Postgres-specific extensions to [`CString`].
+///
+/// This trait is sealed: it cannot be implemented outside the standard library.
+/// This is so that future additional methods are not breaking changes.
+pub trait CStringExt: Sealed {
+    /// Converts `s` to a `CString` in the server encoding, ready to be
+    /// passed to Postgres.
+    fn from_str_to_server(s: &str) -> Result<Self, EncodingError>
+    where
+        Self: Sized;
+}
+
+impl CStringExt for CString {
+    fn from_str_to_server(s: &str) -> Result<CString, EncodingError> {
+        CString::new(to_server(s)?)
+            .map_err(|e| EncodingError::new(EncodingErrorKind::InteriorNul, e.nul_position()))
+    }
+}
@@ -0,0 +1,231 @@
+//! Postgres-specific filesystem functionality.
+
+use crate::collections::BTreeSet;
+use crate::ffi::OsString;
+use crate::io;
+use crate::path::{Component, Path, PathBuf};
+use crate::sys;
+
+/// A Postgres virtual file descriptor, the `File` type from `storage/fd.h`.
+pub type Vfd = crate::ffi::c_int;
+
+/// Postgres's temporary files, as used by the scratch directory.
+///
+/// Once the host installs an implementation with [`set_temporary_files`],
+/// [`env::temp_dir`] names a flat scratch directory and files created in it
+/// are Postgres temporary files, created through [`create`]. They live under
+/// `pgsql_tmp`, count against `temp_file_limit` and are deleted at the end of
+/// the transaction that created them. Handles that outlive the transaction
+/// report [`NotFound`] from then on. No other path is reachable this way.
+///
+/// [`env::temp_dir`]: crate::env::temp_dir
+/// [`create`]: TemporaryFiles::create
+/// [`NotFound`]: io::ErrorKind::NotFound
+pub trait TemporaryFiles: Sync {
+    /// Creates a new, empty temporary file.
+    ///
+    /// This is `OpenTemporaryFile(false)`: the file must be owned by the
+    /// top-level transaction's resource owner, so that Postgres closes and
+    /// deletes it when the transaction ends.
+    fn create(&self) -> io::Result<Vfd>;
+
+    /// Reads from `file` at `offset`, like `FileRead`.
+    fn read_at(&self, file: Vfd, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Writes to `file` at `offset`, like `FileWrite`.
+    ///
+    /// This is where `temp_file_limit` is enforced.
+    fn write_at(&self, file: Vfd, buf: &[u8], offset: u64) -> io::Result<usize>;
+
+    /// Returns the size of `file`, like `FileSize`.
+    fn size(&self, file: Vfd) -> io::Result<u64>;
+
+    /// Truncates or extends `file` to `size` bytes, like `FileTruncate`.
+    fn set_len(&self, file: Vfd, size: u64) -> io::Result<()>;
+
+    /// Closes and deletes `file`, like `FileClose`.
+    ///
+    /// This is only called for files created in the current transaction.
+    fn close(&self, file: Vfd);
+
+    /// Returns an identifier of the current top-level transaction, such as
+    /// `MyProc->lxid`.
+    ///
+    /// Files created under another identifier are assumed to be gone.
+    fn transaction(&self) -> u64;
+}
+
+/// Installs the implementation of [`TemporaryFiles`] that backs the scratch
+/// directory.
+///
+/// Until this is called, [`env::temp_dir`] still returns the scratch
+/// directory, but nothing can be created in it.
+///
+/// # Safety
+///
+/// This lets code running in the backend create temporary files. It must only
+/// be called by the host.
+///
+/// [`env::temp_dir`]: crate::env::temp_dir
+pub unsafe fn set_temporary_files(files: &'static dyn TemporaryFiles) {
+    sys::fs::set_temporary_files(files)
+}
+
+/// Decides who may read the server directories handed to
+/// [`set_server_directories`].
+pub trait ServerFileAccess: Sync {
+    /// Returns whether the current user may read server files.
+    ///
+    /// This should be `has_privs_of_role(GetUserId(),
+    /// ROLE_PG_READ_SERVER_FILES)`, the same check `pg_read_file` and
+    /// `COPY FROM` a file make. It is asked on every path lookup, so it sees
+    /// `SET ROLE` and `SECURITY DEFINER` functions as they happen.
+    fn may_read_server_files(&self) -> bool;
+}
+
+/// Makes the directories in `roots`, and everything below them, readable
+/// through [`std::fs`], for users that pass [`ServerFileAccess`].
+///
+/// Only reading is possible: [`File::open`] with any of the write or create
+/// options fails with [`PermissionDenied`]. [`File::open`], [`metadata`], [`symlink_metadata`],
+/// [`read_dir`] and [`canonicalize`] work on absolute paths that start with
+/// one of the roots. A path may not contain `..`, and one that leads out of
+/// every root once symlinks are resolved is refused, as is any path when the
+/// current user fails the access check; all of these report
+/// [`PermissionDenied`]. Files that are already open stay readable after the
+/// user changes.
+///
+/// Each call replaces the roots from the previous one. The roots must be
+/// absolute.
+///
+/// # Safety
+///
+/// This exposes files of the server to code running in the backend. It must
+/// only be called by the host.
+///
+/// [`std::fs`]: crate::fs
+/// [`File::open`]: crate::fs::File::open
+/// [`metadata`]: crate::fs::metadata
+/// [`symlink_metadata`]: crate::fs::symlink_metadata
+/// [`read_dir`]: crate::fs::read_dir
+/// [`canonicalize`]: crate::fs::canonicalize
+/// [`PermissionDenied`]: io::ErrorKind::PermissionDenied
+pub unsafe fn set_server_directories(roots: Vec<PathBuf>, access: &'static dyn ServerFileAccess) {
+    sys::fs::set_server_directories(roots, access)
+}
+
+/// A read-only tree of files that can be mounted into the filesystem.
+///
+/// Once mounted, with [`set_mounts`] or as the [`embedded`] directory,
+/// [`File::open`], [`metadata`], [`read_dir`], [`canonicalize`] and
+/// [`set_current_dir`] resolve paths below the mount point against it. Paths
+/// are normalized before they get here: they are relative to the mount point,
+/// with `.` and `..` already taken care of, and empty for the mount point
+/// itself. There are no symlinks.
+///
+/// [`embedded`]: set_embedded
+/// [`File::open`]: crate::fs::File::open
+/// [`metadata`]: crate::fs::metadata
+/// [`read_dir`]: crate::fs::read_dir
+/// [`canonicalize`]: crate::fs::canonicalize
+/// [`set_current_dir`]: crate::env::set_current_dir
+pub trait Vfs: Sync {
+    /// Returns the contents of the file at `path`, or `None` if there is no
+    /// file there.
+    fn read(&self, path: &Path) -> Option<&[u8]>;
+
+    /// Returns the names in the directory at `path`, or `None` if there is no
+    /// directory there.
+    fn list(&self, path: &Path) -> Option<Vec<OsString>>;
+}
+
+/// Files compiled into the function, for the [`embedded`] directory.
+///
+/// Files are given as `(path, contents)` pairs, with paths relative to the
+/// mount point; directories are implied by the paths of the files in them.
+/// The host mounts the tree of the function it is about to call.
+///
+/// ```ignore (requires-postgres-target)
+/// use std::os::postgres::fs::{set_embedded, Embedded};
+///
+/// // In the function's crate:
+/// pub static ASSETS: Embedded = Embedded::new(&[
+///     ("zoneinfo/UTC", include_bytes!("zoneinfo/UTC")),
+///     ("words.txt", include_bytes!("words.txt")),
+/// ]);
+///
+/// // In the host, before each call:
+/// unsafe { set_embedded(Some(&ASSETS)) };
+///
+/// // In the function:
+/// let words = std::fs::read_to_string("/embedded/words.txt")?;
+/// ```
+///
+/// [`embedded`]: set_embedded
+#[derive(Debug)]
+pub struct Embedded {
+    files: &'static [(&'static str, &'static [u8])],
+}
+
+impl Embedded {
+    /// Creates a tree of the given files.
+    pub const fn new(files: &'static [(&'static str, &'static [u8])]) -> Embedded {
+        Embedded { files }
+    }
+}
+
+impl Vfs for Embedded {
+    fn read(&self, path: &Path) -> Option<&[u8]> {
+        self.files.iter().find(|(name, _)| Path::new(name) == path).map(|&(_, contents)| contents)
+    }
+
+    fn list(&self, path: &Path) -> Option<Vec<OsString>> {
+        let mut names = BTreeSet::new();
+        for (name, _) in self.files {
+            if let Ok(rest) = Path::new(name).strip_prefix(path) {
+                if let Some(Component::Normal(first)) = rest.components().next() {
+                    names.insert(first.to_owned());
+                }
+            }
+        }
+        // The mount point is there even when there is nothing in it.
+        if names.is_empty() && !path.as_os_str().is_empty() {
+            return None;
+        }
+        Some(names.into_iter().collect())
+    }
+}
+
+/// Where [`set_embedded`] mounts its files.
+pub const EMBEDDED_DIR: &str = "/embedded";
+
+/// Mounts `files` at [`EMBEDDED_DIR`], replacing whatever was embedded before,
+/// or unmounts it with `None`.
+///
+/// A mount set up with [`set_mounts`] at the same place takes precedence.
+/// Like changing the mounts, this forgets the current directory.
+///
+/// # Safety
+///
+/// The embedded directory is shared by every function the backend calls. This
+/// must only be called by the host, with the files of the function it is about
+/// to call.
+pub unsafe fn set_embedded(files: Option<&'static Embedded>) {
+    sys::fs::set_embedded(files)
+}
+
+/// Replaces the mount table with `mounts`, each a [`Vfs`] and the absolute
+/// path it is mounted at.
+///
+/// A path belongs to the mount with the longest matching mount point. Mounts
+/// come before the scratch directory and the server directories, so they can
+/// hide parts of those. Changing the mounts also forgets the current
+/// directory.
+///
+/// # Safety
+///
+/// Mounts decide what code running in the backend finds in the filesystem.
+/// This must only be called by the host.
+pub unsafe fn set_mounts(mounts: Vec<(PathBuf, &'static dyn Vfs)>) {
+    sys::fs::set_mounts(mounts)
+}
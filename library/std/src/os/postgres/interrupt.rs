@@ -0,0 +1,69 @@
+//! Noticing query cancels inside long-running operations.
+//!
+//! Postgres only acts on a cancel request, a `statement_timeout` or a
+//! terminate request at a `CHECK_FOR_INTERRUPTS()`, which Rust code never
+//! reaches until it returns. Once the host installs [`Interrupts`], `std`
+//! asks it at points that are safe to unwind from: on every few hundredth
+//! pass of [`io::copy`], on each wait of a [`Condvar`], and on every few
+//! hundredth allocation. The allocator must not unwind, so there a pending
+//! interrupt fails the allocation, and the unwind starts from
+//! [`handle_alloc_error`], as it does when memory runs out. That covers
+//! growing collections, `collect` and formatting, but only as often as they
+//! allocate: [`try_reserve`] and the like return an error instead of
+//! unwinding, and work that doesn't allocate, such as sorting or searching,
+//! never asks, so functions that spend long in it ask themselves with
+//! [`check_for_interrupts`] between steps.
+//!
+//! A pending interrupt unwinds with an [`Interrupted`] payload, without
+//! running the panic hook or the alloc error hook. The host catches it at the function boundary and
+//! calls `CHECK_FOR_INTERRUPTS()` there, which raises the proper error.
+//!
+//! [`io::copy`]: crate::io::copy
+//! [`Condvar`]: crate::sync::Condvar
+//! [`handle_alloc_error`]: crate::alloc::handle_alloc_error
+//! [`try_reserve`]: crate::vec::Vec::try_reserve
+
+use crate::fmt;
+use crate::sys;
+
+/// The backend's pending interrupts.
+pub trait Interrupts: Sync {
+    /// Returns whether `CHECK_FOR_INTERRUPTS()` would act on an interrupt
+    /// now, for example whether `QueryCancelPending` or `ProcDiePending` is
+    /// set and interrupts are not held off.
+    ///
+    /// This must not process the interrupt itself.
+    fn pending(&self) -> bool;
+}
+
+/// Installs the host's [`Interrupts`], or removes them with `None`, after
+/// which interrupts are never noticed, and waiting on a [`Condvar`] panics,
+/// since nothing could end the wait.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+///
+/// [`Condvar`]: crate::sync::Condvar
+pub unsafe fn set_interrupts(interrupts: Option<&'static dyn Interrupts>) {
+    sys::interrupt::set_interrupts(interrupts)
+}
+
+/// Unwinds with an [`Interrupted`] payload if the backend has an interrupt
+/// pending.
+///
+/// Long loops, such as one sorting a large slice in chunks, can call this now
+/// and then. It does nothing while the thread is already unwinding.
+pub fn check_for_interrupts() {
+    sys::interrupt::check_now()
+}
+
+/// The payload `std` unwinds with when it notices a pending interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("interrupted by a pending Postgres interrupt")
+    }
+}
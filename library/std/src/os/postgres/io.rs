@@ -0,0 +1,576 @@
+//! Postgres-specific I/O functionality.
+
+use crate::fmt;
+use crate::io::{self, Read, Seek, SeekFrom, Write};
+use crate::mem;
+use crate::sync::{Arc, Mutex, PoisonError, RwLock};
+use crate::sys;
+use crate::sys::unsupported;
+
+/// A Postgres object identifier, `Oid`.
+pub type Oid = u32;
+
+/// A large object descriptor, as returned by `lo_open`.
+pub type LoFd = crate::ffi::c_int;
+
+/// Postgres's large objects, as used by [`LargeObject`].
+///
+/// Descriptors are the ones the SQL-level `lo_*` functions use: they belong to
+/// the transaction that opened them and are closed when it ends.
+pub trait LargeObjects: Sync {
+    /// Opens the large object `oid` for reading, and for writing too if
+    /// `write` is set, like `lo_open` with `INV_READ` and `INV_WRITE`.
+    ///
+    /// This must make the privilege checks `lo_open` makes: `SELECT` on the
+    /// large object to read it, `UPDATE` to write it, unless
+    /// `lo_compat_privileges` is on.
+    fn open(&self, oid: Oid, write: bool) -> io::Result<LoFd>;
+
+    /// Reads from the current position of `fd`, like `lo_read`.
+    ///
+    /// `buf` is never longer than `c_int::MAX` bytes.
+    fn read(&self, fd: LoFd, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes at the current position of `fd`, like `lo_write`.
+    ///
+    /// `buf` is never longer than `c_int::MAX` bytes.
+    fn write(&self, fd: LoFd, buf: &[u8]) -> io::Result<usize>;
+
+    /// Moves the position of `fd` and returns the new one, like `lo_lseek64`.
+    fn seek(&self, fd: LoFd, pos: SeekFrom) -> io::Result<u64>;
+
+    /// Truncates or extends the large object to `len` bytes, like
+    /// `lo_truncate64`.
+    fn truncate(&self, fd: LoFd, len: u64) -> io::Result<()>;
+
+    /// Closes `fd`, like `lo_close`.
+    ///
+    /// This is only called for descriptors opened in the current transaction.
+    fn close(&self, fd: LoFd);
+
+    /// Returns an identifier of the current top-level transaction, such as
+    /// `MyProc->lxid`.
+    ///
+    /// Descriptors opened under another identifier are assumed to be closed.
+    fn transaction(&self) -> u64;
+}
+
+static LARGE_OBJECTS: RwLock<Option<&'static dyn LargeObjects>> = RwLock::new(None);
+
+/// Installs the implementation of [`LargeObjects`] that backs [`LargeObject`].
+///
+/// Until this is called, [`LargeObject::open`] returns an error.
+///
+/// # Safety
+///
+/// This lets code running in the backend read and write large objects, subject
+/// to the checks [`LargeObjects::open`] makes. It must only be called by the
+/// host.
+pub unsafe fn set_large_objects(objects: &'static dyn LargeObjects) {
+    *LARGE_OBJECTS.write().unwrap_or_else(PoisonError::into_inner) = Some(objects);
+}
+
+/// An open Postgres large object.
+///
+/// This implements [`Read`], [`Write`] and [`Seek`] over the object's
+/// contents, so that it can be streamed instead of loaded into memory at
+/// once. Every call goes to Postgres and the large object's pages, with no
+/// buffering in between; wrap it in a [`BufReader`] or [`BufWriter`] for
+/// small reads and writes.
+///
+/// The object is closed when this is dropped, or at the end of the transaction
+/// that opened it, whichever comes first. After that, everything returns an
+/// error.
+///
+/// [`BufReader`]: io::BufReader
+/// [`BufWriter`]: io::BufWriter
+pub struct LargeObject {
+    backend: &'static dyn LargeObjects,
+    fd: LoFd,
+    xact: u64,
+    write: bool,
+}
+
+impl LargeObject {
+    /// Opens the large object `oid` for reading.
+    ///
+    /// Fails with [`PermissionDenied`] unless the current user may read it.
+    ///
+    /// [`PermissionDenied`]: io::ErrorKind::PermissionDenied
+    pub fn open(oid: Oid) -> io::Result<LargeObject> {
+        LargeObject::open_with(oid, false)
+    }
+
+    /// Opens the large object `oid` for reading and writing.
+    ///
+    /// Fails with [`PermissionDenied`] unless the current user may update it.
+    ///
+    /// [`PermissionDenied`]: io::ErrorKind::PermissionDenied
+    pub fn open_writable(oid: Oid) -> io::Result<LargeObject> {
+        LargeObject::open_with(oid, true)
+    }
+
+    fn open_with(oid: Oid, write: bool) -> io::Result<LargeObject> {
+        let backend = match *LARGE_OBJECTS.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(backend) => backend,
+            None => return unsupported(),
+        };
+        let fd = backend.open(oid, write)?;
+        Ok(LargeObject { backend, fd, xact: backend.transaction(), write })
+    }
+
+    fn fd(&self) -> io::Result<LoFd> {
+        if self.backend.transaction() != self.xact {
+            return Err(io::const_io_error!(
+                io::ErrorKind::NotFound,
+                "large object was closed at the end of its transaction",
+            ));
+        }
+        Ok(self.fd)
+    }
+
+    fn writable_fd(&self) -> io::Result<LoFd> {
+        if !self.write {
+            return Err(io::const_io_error!(
+                io::ErrorKind::PermissionDenied,
+                "large object was not opened for writing",
+            ));
+        }
+        self.fd()
+    }
+
+    /// Truncates or extends the large object to `size` bytes.
+    ///
+    /// Extending it fills the new space with zeros. The position is left
+    /// where it was.
+    pub fn set_len(&self, size: u64) -> io::Result<()> {
+        self.backend.truncate(self.writable_fd()?, size)
+    }
+}
+
+// `lo_read` and `lo_write` take their length as a C `int`.
+const MAX_LEN: usize = crate::ffi::c_int::MAX as usize;
+
+impl Read for LargeObject {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_LEN);
+        self.backend.read(self.fd()?, &mut buf[..len])
+    }
+}
+
+impl Write for LargeObject {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_LEN);
+        self.backend.write(self.writable_fd()?, &buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Writes go straight to the large object's pages.
+        Ok(())
+    }
+}
+
+impl Seek for LargeObject {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.backend.seek(self.fd()?, pos)
+    }
+}
+
+impl Drop for LargeObject {
+    fn drop(&mut self) {
+        if let Ok(fd) = self.fd() {
+            self.backend.close(fd);
+        }
+    }
+}
+
+impl fmt::Debug for LargeObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LargeObject").field("fd", &self.fd).field("write", &self.write).finish()
+    }
+}
+
+/// A pointer to a varlena datum, `struct varlena *` in Postgres, which may be
+/// TOASTed: compressed, stored out of line, or both.
+pub type Varlena = *const crate::ffi::c_void;
+
+/// Postgres's detoasting, as used by [`VarlenaReader`].
+pub trait Detoast: Sync {
+    /// Returns the length of the value's data, without its header, like
+    /// `toast_raw_datum_size` minus `VARHDRSZ`.
+    fn len(&self, value: Varlena) -> io::Result<u64>;
+
+    /// Copies the value's data from `offset` into `buf`, like
+    /// `detoast_attr_slice(value, offset, buf.len())`, and returns how many
+    /// bytes that was.
+    ///
+    /// This only copies fewer than `buf.len()` bytes at the end of the value.
+    fn read_at(&self, value: Varlena, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+static DETOAST: RwLock<Option<&'static dyn Detoast>> = RwLock::new(None);
+
+/// Installs the implementation of [`Detoast`] that backs [`VarlenaReader`].
+///
+/// Until this is called, [`VarlenaReader::new`] returns an error.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_detoast(detoast: &'static dyn Detoast) {
+    *DETOAST.write().unwrap_or_else(PoisonError::into_inner) = Some(detoast);
+}
+
+/// Reads a varlena value, such as a `bytea` or `text`, without detoasting all
+/// of it at once.
+///
+/// This implements [`Read`] and [`Seek`] over the value's data. Every read
+/// fetches just the slice it needs, never more than 64 KiB at a time, so
+/// streaming through a value takes memory in proportion to the buffer it is
+/// read into, not to the value.
+///
+/// That works best for values stored out of line without compression
+/// (`STORAGE EXTERNAL`), where a slice only fetches the TOAST chunks it
+/// covers. A compressed value has to be decompressed from its start up to
+/// the end of each slice, which makes a full pass over it quadratic in its
+/// size.
+pub struct VarlenaReader {
+    backend: &'static dyn Detoast,
+    value: Varlena,
+    len: u64,
+    pos: u64,
+}
+
+// The most a single read asks `detoast_attr_slice` for, which pallocs the
+// whole slice before it's copied out.
+const MAX_SLICE: usize = 64 * 1024;
+
+impl VarlenaReader {
+    /// Creates a reader over `value`.
+    ///
+    /// # Safety
+    ///
+    /// `value` must point to a varlena datum that stays valid, and unchanged,
+    /// for as long as the reader is used: usually one the function was called
+    /// with. The TOAST data it points to must stay valid too, which holds
+    /// for the rest of the statement.
+    pub unsafe fn new(value: Varlena) -> io::Result<VarlenaReader> {
+        let backend = match *DETOAST.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(backend) => backend,
+            None => return unsupported(),
+        };
+        let len = backend.len(value)?;
+        Ok(VarlenaReader { backend, value, len, pos: 0 })
+    }
+
+    /// Returns the length of the value's data, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether the value's data is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for VarlenaReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.pos);
+        let len = buf.len().min(MAX_SLICE).min(usize::try_from(left).unwrap_or(usize::MAX));
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = self.backend.read_at(self.value, self.pos, &mut buf[..len])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for VarlenaReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = new.ok_or_else(|| {
+            io::const_io_error!(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.pos)
+    }
+}
+
+impl fmt::Debug for VarlenaReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VarlenaReader").field("len", &self.len).field("pos", &self.pos).finish()
+    }
+}
+
+/// Values that can be written out as bytes and read back, to be kept outside
+/// the memory of the backend that made them.
+///
+/// [`decode`](Encode::decode) is handed exactly the bytes
+/// [`encode`](Encode::encode) appended, possibly in another backend running
+/// the same code, and must not trust them any further than that: bytes it
+/// can't make sense of are an [`InvalidData`](io::ErrorKind::InvalidData)
+/// error, not a panic.
+pub trait Encode: Sized {
+    /// Appends the bytes of `self` to `buf`.
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    /// Rebuilds a value from the bytes `encode` appended.
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
+}
+
+fn invalid_encoding() -> io::Error {
+    io::const_io_error!(io::ErrorKind::InvalidData, "invalid encoded value")
+}
+
+macro_rules! encode_num {
+    ($($t:ty)*) => {$(
+        impl Encode for $t {
+            fn encode(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> io::Result<$t> {
+                bytes.try_into().map(<$t>::from_le_bytes).map_err(|_| invalid_encoding())
+            }
+        }
+    )*};
+}
+
+encode_num! { u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64 }
+
+impl Encode for usize {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as u64).encode(buf)
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<usize> {
+        usize::try_from(u64::decode(bytes)?).map_err(|_| invalid_encoding())
+    }
+}
+
+impl Encode for isize {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as i64).encode(buf)
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<isize> {
+        isize::try_from(i64::decode(bytes)?).map_err(|_| invalid_encoding())
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8)
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<bool> {
+        match bytes {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(invalid_encoding()),
+        }
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as u32).encode(buf)
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<char> {
+        char::from_u32(u32::decode(bytes)?).ok_or_else(invalid_encoding)
+    }
+}
+
+impl Encode for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self)
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes())
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<String> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid_encoding())
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        if let Some(value) = self {
+            buf.push(1);
+            value.encode(buf);
+        } else {
+            buf.push(0);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Option<T>> {
+        match bytes.split_first() {
+            Some((0, [])) => Ok(None),
+            Some((1, rest)) => T::decode(rest).map(Some),
+            _ => Err(invalid_encoding()),
+        }
+    }
+}
+
+// The fields of a tuple are each preceded by their length as a `u64`, which
+// any field fits in, except the last, which takes up the rest.
+macro_rules! encode_tuple {
+    ($($name:ident)* ; $last:ident) => {
+        impl<$($name: Encode,)* $last: Encode> Encode for ($($name,)* $last,) {
+            #[allow(non_snake_case)]
+            fn encode(&self, buf: &mut Vec<u8>) {
+                let ($($name,)* $last,) = self;
+                $(
+                    let at = buf.len();
+                    buf.extend_from_slice(&[0; 8]);
+                    $name.encode(buf);
+                    let len = (buf.len() - at - 8) as u64;
+                    buf[at..at + 8].copy_from_slice(&len.to_le_bytes());
+                )*
+                $last.encode(buf);
+            }
+
+            #[allow(non_snake_case)]
+            fn decode(bytes: &[u8]) -> io::Result<Self> {
+                let mut rest = bytes;
+                $(
+                    let len = rest.get(..8).ok_or_else(invalid_encoding)?;
+                    let len = u64::from_le_bytes(len.try_into().unwrap());
+                    let len = usize::try_from(len).map_err(|_| invalid_encoding())?;
+                    let field = rest.get(8..).and_then(|rest| rest.get(..len));
+                    let $name = $name::decode(field.ok_or_else(invalid_encoding)?)?;
+                    rest = &rest[8 + len..];
+                )*
+                Ok(($($name,)* $last::decode(rest)?,))
+            }
+        }
+    };
+}
+
+encode_tuple! { A ; B }
+encode_tuple! { A B ; C }
+encode_tuple! { A B C ; D }
+
+/// Starts capturing what the current call prints, and returns the
+/// [`Capture`] it goes into.
+///
+/// Until the capture is finished or dropped, everything [`print!`],
+/// [`println!`], [`eprint!`] and [`eprintln!`] write goes into it, along with
+/// the message of every panic, `panicked at '...', src/lib.rs:1:1` and a
+/// newline. It keeps at most `limit` bytes; whatever comes after that is
+/// dropped, possibly in the middle of a character.
+///
+/// Captures nest: a capture started while another is going on takes over
+/// until it is finished, and the other then picks up where it left off. A
+/// capture finished before one started after it leaves that one going on,
+/// which then picks up where the finished one would have once it finishes.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn start_capture(limit: usize) -> Capture {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let previous = io::set_output_capture(Some(buffer.clone()));
+    let previous_limit = sys::stdio::set_capture_limit(limit);
+    captures().push(Started { buffer: buffer.clone(), previous, previous_limit });
+    Capture { buffer, finished: false }
+}
+
+type Buffer = Arc<Mutex<Vec<u8>>>;
+
+// The captures going on, the latest last, each with whatever was capturing
+// output before it, which is restored once it finishes.
+static CAPTURES: Mutex<Vec<Started>> = Mutex::new(Vec::new());
+
+struct Started {
+    buffer: Buffer,
+    previous: Option<Buffer>,
+    previous_limit: usize,
+}
+
+fn captures() -> crate::sync::MutexGuard<'static, Vec<Started>> {
+    CAPTURES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Printed output being captured, as started by [`start_capture`].
+///
+/// Dropping this finishes the capture, and discards what it holds.
+pub struct Capture {
+    buffer: Buffer,
+    finished: bool,
+}
+
+impl Capture {
+    /// Returns what has been captured so far, and clears it, which makes
+    /// room for up to the limit again.
+    pub fn take(&self) -> Vec<u8> {
+        mem::take(&mut *self.buffer.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Stops capturing, and returns what has been captured since it started
+    /// or since it was last taken.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.restore();
+        self.take()
+    }
+
+    fn restore(&mut self) {
+        if mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let mut captures = captures();
+        let Some(i) = captures.iter().position(|c| Arc::ptr_eq(&c.buffer, &self.buffer)) else {
+            return;
+        };
+        let started = captures.remove(i);
+        if let Some(next) = captures.get_mut(i) {
+            // The capture started after this one is still going on, and goes
+            // back to what this one would have.
+            next.previous = started.previous;
+            next.previous_limit = started.previous_limit;
+            return;
+        }
+        drop(captures);
+        // Only put the previous capture back if output still goes here.
+        let current = io::set_output_capture(None);
+        if current.as_ref().map_or(false, |current| Arc::ptr_eq(current, &self.buffer)) {
+            sys::stdio::set_capture_limit(started.previous_limit);
+            drop(io::set_output_capture(started.previous));
+        } else {
+            drop(io::set_output_capture(current));
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Capture").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,35 @@
+//! Postgres-specific extensions to `std`.
+//!
+//! On postgres targets most of what `std` can reach is fixed when the
+//! standard library is built: anything that would let code escape the backend
+//! it runs in returns `Err` instead. The items in this module let the host
+//! that loads Rust code into Postgres (usually PL/Rust) open specific holes in
+//! that sandbox.
+//!
+//! Everything that widens access is an `unsafe fn`, which keeps it out of
+//! reach of code that is compiled without `unsafe`. The host is expected to
+//! call these from its own code, never on behalf of the functions it runs.
+//!
+//! Some features need the host to call into Postgres for them, and take a
+//! `&'static dyn Trait` the host implements. Those implementations are called
+//! from inside `std`, so they must not let a Postgres `ERROR` longjmp through
+//! their caller: failures are reported through the return value, or as a Rust
+//! panic.
+
+#![unstable(feature = "postgres_ext", issue = "none")]
+#![doc(cfg(target_family = "postgres"))]
+
+pub mod alloc;
+pub mod env;
+pub mod ffi;
+pub mod fs;
+pub mod interrupt;
+pub mod io;
+pub mod net;
+pub mod panic;
+pub mod process;
+pub mod replay;
+pub mod spill;
+pub mod sync;
+pub mod time;
+pub mod volatility;
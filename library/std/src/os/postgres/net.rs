@@ -0,0 +1,27 @@
+//! Postgres-specific networking functionality.
+
+use crate::path::PathBuf;
+use crate::sys;
+
+/// Replaces the set of socket paths that [`UnixStream::connect`] may reach.
+///
+/// A connection is allowed only if the path passed to `connect` is
+/// byte-for-byte equal to one of `paths`. Neither side is normalized, so
+/// relative paths, `..` components or a different spelling of an allowed
+/// path are all rejected with [`PermissionDenied`]. Only `connect` consults
+/// the list: binding, listening, datagram sockets, ancillary data (and with
+/// it passing file descriptors) and peer credentials stay unsupported.
+///
+/// The allowlist starts out empty. Passing an empty `Vec` blocks every
+/// connection again, though already connected streams stay open.
+///
+/// # Safety
+///
+/// This lets code running in the backend talk to whatever listens on
+/// `paths`. It must only be called by the host, with paths it trusts.
+///
+/// [`UnixStream::connect`]: crate::os::unix::net::UnixStream::connect
+/// [`PermissionDenied`]: crate::io::ErrorKind::PermissionDenied
+pub unsafe fn set_unix_socket_allowlist(paths: Vec<PathBuf>) {
+    sys::net::set_unix_socket_allowlist(paths)
+}
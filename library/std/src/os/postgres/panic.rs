@@ -0,0 +1,39 @@
+//! Panic hooks that last for one call.
+//!
+//! Every function that runs in a backend shares its panic hook, so a hook
+//! one function registers with [`set_hook`] would otherwise go on running
+//! for the panics of every function after it. The host runs each call with
+//! [`with_call_hook`] instead, which puts the hook back the way it was once
+//! the call returns or unwinds.
+//!
+//! The host's own hook, which the host registers with [`set_host_hook`],
+//! runs for every panic before the hook of the call, whatever hook the call
+//! has registered, so no function can take it out of the chain. A panic
+//! inside a hook cuts the hook short, rather than aborting the backend, and
+//! the panic it was called for goes on as usual.
+//!
+//! [`set_hook`]: crate::panic::set_hook
+
+use crate::panic::PanicInfo;
+use crate::panicking;
+
+/// Runs `f`, one call into a function, with the default panic hook
+/// registered, and registers the hook that was registered before once `f`
+/// returns or unwinds.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn with_call_hook<R>(f: impl FnOnce() -> R) -> R {
+    panicking::with_scoped_hook(f)
+}
+
+/// Registers the hook that runs for every panic before the hook of the
+/// call, or none with `None`.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_host_hook(hook: Option<Box<dyn Fn(&PanicInfo<'_>) + 'static + Sync + Send>>) {
+    panicking::set_host_hook(hook)
+}
@@ -0,0 +1,42 @@
+//! What becomes of the process-wide operations in [`std::process`].
+//!
+//! A function has no process of its own to end: the backend it runs in goes
+//! on to serve the rest of the session. [`process::exit`] unwinds out of the
+//! call instead, with an [`Exit`] as its payload, and runs no panic hook on
+//! the way. The host catches it at the function boundary like any other
+//! unwind, and decides what the call returns, or what it reports.
+//!
+//! [`process::id`] is not the backend's pid, which would let a function tell
+//! other sessions which backend is its own, but a hash of it under a secret
+//! key. It stays the same for the whole session, and is all but certainly
+//! different from the id of every other backend running at the same time, so
+//! it can still go into unique names.
+//!
+//! [`std::process`]: crate::process
+//! [`process::exit`]: crate::process::exit
+//! [`process::id`]: crate::process::id
+
+use crate::fmt;
+
+/// The payload [`process::exit`](crate::process::exit) unwinds with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exit {
+    code: i32,
+}
+
+impl Exit {
+    pub(crate) fn new(code: i32) -> Exit {
+        Exit { code }
+    }
+
+    /// Returns the code the function asked to exit with.
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "process::exit({}) called", self.code)
+    }
+}
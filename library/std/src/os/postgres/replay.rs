@@ -0,0 +1,72 @@
+//! Recording and replaying the nondeterminism `std` lets into a backend.
+//!
+//! Given the same arguments, a function can only behave differently from one
+//! run to the next through the values `std` hands it from outside: clock
+//! readings, hash keys and random bytes. In [`Mode::Record`] each of those is
+//! passed to a [`Recorder`] as it is handed out. In [`Mode::Replay`] they come
+//! from a [`Replayer`] instead, in the same order, so that a run recorded in
+//! production can be reproduced somewhere else.
+//!
+//! While replaying, [`thread::sleep`] returns right away, since the clock
+//! readings that follow it are replayed too.
+//!
+//! [`thread::sleep`]: crate::thread::sleep
+
+use crate::sys;
+
+/// A value `std` handed out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Value {
+    /// A reading of [`Instant::now`](crate::time::Instant::now): the
+    /// monotonic clock, in seconds and nanoseconds since some unspecified
+    /// point.
+    Instant { secs: i64, nanos: u32 },
+    /// A reading of [`SystemTime::now`](crate::time::SystemTime::now), in
+    /// seconds and nanoseconds since the Unix epoch.
+    SystemTime { secs: i64, nanos: u32 },
+    /// The keys of a new [`RandomState`](crate::collections::hash_map::RandomState).
+    HashKeys(u64, u64),
+    /// Random bytes from the kernel.
+    RandomBytes(Vec<u8>),
+}
+
+/// Where recorded values go.
+pub trait Recorder: Sync {
+    /// Takes the next value handed out.
+    fn record(&self, value: &Value);
+}
+
+/// Where replayed values come from.
+pub trait Replayer: Sync {
+    /// Returns the next recorded value, or `None` once there are no more.
+    fn next(&self) -> Option<Value>;
+
+    /// Reports that the run went differently than the recording: `std` was
+    /// about to hand out something other than `found`, which is `None` if
+    /// the recording had run out.
+    ///
+    /// Replaying stops there, as if the mode had been set to
+    /// [`Mode::Off`], and real values are handed out from then on.
+    fn diverged(&self, found: Option<Value>);
+}
+
+/// What happens to the values `std` hands out.
+#[derive(Clone, Copy)]
+pub enum Mode {
+    /// They are read from the system, as usual.
+    Off,
+    /// They are read from the system, and passed to the recorder.
+    Record(&'static dyn Recorder),
+    /// They are taken from the replayer instead.
+    Replay(&'static dyn Replayer),
+}
+
+/// Sets what happens to the values `std` hands out from now on.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_mode(mode: Mode) {
+    sys::replay::set_mode(mode)
+}
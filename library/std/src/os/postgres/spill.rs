@@ -0,0 +1,426 @@
+//! Collections that move to disk when they outgrow `work_mem`.
+//!
+//! Postgres's own sorts and hash tables keep what fits in `work_mem` in
+//! memory and write the rest to temporary files. [`SpillVec`] and
+//! [`ExternalSort`] do the same for Rust code, in the encoding [`Encode`]
+//! gives their values. They use the same Postgres temporary files as the
+//! scratch directory, so they need the host to have installed
+//! [`TemporaryFiles`] before they spill, and what they write counts against
+//! `temp_file_limit`.
+//!
+//! Temporary files belong to the transaction that created them, and Postgres
+//! deletes them when it ends. A collection that outlives its transaction
+//! returns an error when it next needs its file.
+//!
+//! [`TemporaryFiles`]: crate::os::postgres::fs::TemporaryFiles
+
+use crate::cmp::Reverse;
+use crate::collections::BinaryHeap;
+use crate::fmt;
+use crate::fs::File;
+use crate::io;
+use crate::marker::PhantomData;
+use crate::mem;
+use crate::os::postgres::io::Encode;
+use crate::os::unix::fs::FileExt;
+use crate::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use crate::sys;
+use crate::sys_common::FromInner;
+use crate::vec;
+
+// `work_mem` as the host last set it, 4 MB until then, as in Postgres.
+static WORK_MEM: AtomicUsize = AtomicUsize::new(4 * 1024 * 1024);
+
+/// Sets the budget of collections created with `new` from now on, in bytes.
+///
+/// The host calls this at the start of every call with the value of
+/// `work_mem`, or of `hash_mem_multiplier * work_mem` if it likes. It is
+/// 4 MB until then.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_work_mem(bytes: usize) {
+    WORK_MEM.store(bytes, Relaxed);
+}
+
+/// A temporary file that is only ever appended to.
+struct SpillFile {
+    file: File,
+    len: u64,
+}
+
+impl SpillFile {
+    fn create() -> io::Result<SpillFile> {
+        let file = File::from_inner(sys::fs::unnamed_temp_file()?);
+        Ok(SpillFile { file, len: 0 })
+    }
+
+    fn append(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(buf, self.len)?;
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.file.read_exact_at(buf, offset)
+    }
+}
+
+// How much is read from or written to a file at a time: a Postgres block.
+const CHUNK: usize = 8192;
+
+/// Appends `value` to `buf`, preceded by its length as a `u32`.
+fn push_record<T: Encode>(buf: &mut Vec<u8>, value: &T) -> io::Result<()> {
+    let at = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    value.encode(buf);
+    match u32::try_from(buf.len() - at - 4) {
+        Ok(len) => {
+            buf[at..at + 4].copy_from_slice(&len.to_le_bytes());
+            Ok(())
+        }
+        Err(_) => {
+            buf.truncate(at);
+            Err(io::const_io_error!(
+                io::ErrorKind::InvalidInput,
+                "encoded value is larger than 4 GiB",
+            ))
+        }
+    }
+}
+
+/// Splits the record at the start of `buf` off, if all of it is there, and
+/// returns its contents and its length with the header.
+fn split_record(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let len = u32::from_le_bytes(buf.get(..4)?.try_into().unwrap()) as usize;
+    Some((buf.get(4..4 + len)?, 4 + len))
+}
+
+/// Reads the records in a range of a file one at a time.
+struct Records {
+    next: u64,
+    end: u64,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Records {
+    fn new(start: u64, end: u64) -> Records {
+        Records { next: start, end, buf: Vec::new(), pos: 0 }
+    }
+
+    fn next<T: Encode>(&mut self, file: &SpillFile) -> io::Result<Option<T>> {
+        loop {
+            if let Some((record, len)) = split_record(&self.buf[self.pos..]) {
+                let value = T::decode(record)?;
+                self.pos += len;
+                return Ok(Some(value));
+            }
+            if self.next == self.end {
+                return if self.pos == self.buf.len() {
+                    Ok(None)
+                } else {
+                    Err(io::const_io_error!(
+                        io::ErrorKind::InvalidData,
+                        "temporary file ends in the middle of a value",
+                    ))
+                };
+            }
+            // Keep what is left of the record, and read at least enough to
+            // finish it.
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+            let want = match self.buf.get(..4) {
+                Some(len) => 4 + u32::from_le_bytes(len.try_into().unwrap()) as usize,
+                None => 4,
+            };
+            let left = usize::try_from(self.end - self.next).unwrap_or(usize::MAX);
+            let n = want.saturating_sub(self.buf.len()).max(CHUNK).min(left);
+            let at = self.buf.len();
+            self.buf.resize(at + n, 0);
+            file.read_exact_at(&mut self.buf[at..], self.next)?;
+            self.next += n as u64;
+        }
+    }
+}
+
+/// An append-only vector that moves its contents to a temporary file when
+/// they outgrow `work_mem`.
+///
+/// Values are encoded as they are pushed, so what counts against the budget
+/// is their encoded size, and reading them back decodes them again. Once the
+/// budget is reached, everything pushed so far is written to disk, and
+/// memory use starts over.
+pub struct SpillVec<T> {
+    buf: Vec<u8>,
+    file: Option<SpillFile>,
+    len: usize,
+    budget: usize,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+impl<T: Encode> SpillVec<T> {
+    /// Creates an empty vector that keeps up to `work_mem` in memory, as set
+    /// with [`set_work_mem`].
+    pub fn new() -> SpillVec<T> {
+        SpillVec::with_budget(WORK_MEM.load(Relaxed))
+    }
+
+    /// Creates an empty vector that keeps up to `budget` bytes in memory.
+    pub fn with_budget(budget: usize) -> SpillVec<T> {
+        SpillVec { buf: Vec::new(), file: None, len: 0, budget, _marker: PhantomData }
+    }
+
+    /// Appends `value`, writing everything out to disk if that takes the
+    /// vector over its budget.
+    pub fn push(&mut self, value: T) -> io::Result<()> {
+        push_record(&mut self.buf, &value)?;
+        self.len += 1;
+        if self.buf.len() > self.budget {
+            let file = match &mut self.file {
+                Some(file) => file,
+                file @ None => file.insert(SpillFile::create()?),
+            };
+            file.append(&self.buf)?;
+            self.buf = Vec::new();
+        }
+        Ok(())
+    }
+
+    /// Returns the number of values in the vector.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the vector holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns `true` if some of the vector is on disk.
+    pub fn is_spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Returns an iterator over the values, in the order they were pushed.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let end = self.file.as_ref().map_or(0, |file| file.len);
+        Iter { vec: self, file: Records::new(0, end), pos: 0, done: false }
+    }
+}
+
+impl<T: Encode> Default for SpillVec<T> {
+    fn default() -> SpillVec<T> {
+        SpillVec::new()
+    }
+}
+
+impl<T> fmt::Debug for SpillVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpillVec")
+            .field("len", &self.len)
+            .field("spilled", &self.file.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An iterator over the values of a [`SpillVec`], decoding each in turn.
+///
+/// It stops after the first error.
+pub struct Iter<'a, T> {
+    vec: &'a SpillVec<T>,
+    file: Records,
+    pos: usize,
+    done: bool,
+}
+
+impl<T: Encode> Iterator for Iter<'_, T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        if self.done {
+            return None;
+        }
+        let value = match &self.vec.file {
+            Some(file) => self.file.next(file).transpose(),
+            None => None,
+        };
+        let value = value.or_else(|| {
+            let (record, len) = split_record(&self.vec.buf[self.pos..])?;
+            self.pos += len;
+            Some(T::decode(record))
+        });
+        self.done = !matches!(value, Some(Ok(_)));
+        value
+    }
+}
+
+impl<T> fmt::Debug for Iter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Iter").finish_non_exhaustive()
+    }
+}
+
+/// A sort that moves its input to a temporary file, in sorted runs, when it
+/// outgrows `work_mem`, and merges the runs at the end.
+///
+/// Values are kept as they are until a run is written out, so what counts
+/// against the budget is their encoded size plus their size in memory.
+/// The sort is stable.
+pub struct ExternalSort<T> {
+    values: Vec<T>,
+    used: usize,
+    budget: usize,
+    file: Option<SpillFile>,
+    runs: Vec<(u64, u64)>,
+    scratch: Vec<u8>,
+}
+
+impl<T: Encode + Ord> ExternalSort<T> {
+    /// Creates an empty sort that keeps up to `work_mem` in memory, as set
+    /// with [`set_work_mem`].
+    pub fn new() -> ExternalSort<T> {
+        ExternalSort::with_budget(WORK_MEM.load(Relaxed))
+    }
+
+    /// Creates an empty sort that keeps up to `budget` bytes in memory.
+    pub fn with_budget(budget: usize) -> ExternalSort<T> {
+        ExternalSort {
+            values: Vec::new(),
+            used: 0,
+            budget,
+            file: None,
+            runs: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Adds `value` to the sort, writing a sorted run out to disk if that
+    /// takes it over its budget.
+    pub fn push(&mut self, value: T) -> io::Result<()> {
+        self.scratch.clear();
+        value.encode(&mut self.scratch);
+        self.used += self.scratch.len() + mem::size_of::<T>();
+        self.values.push(value);
+        if self.used > self.budget {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    fn write_run(&mut self) -> io::Result<()> {
+        self.values.sort();
+        let file = match &mut self.file {
+            Some(file) => file,
+            file @ None => file.insert(SpillFile::create()?),
+        };
+        let start = file.len;
+        let mut buf = Vec::with_capacity(CHUNK);
+        for value in self.values.drain(..) {
+            push_record(&mut buf, &value)?;
+            if buf.len() >= CHUNK {
+                file.append(&buf)?;
+                buf.clear();
+            }
+        }
+        file.append(&buf)?;
+        self.runs.push((start, file.len));
+        self.used = 0;
+        Ok(())
+    }
+
+    /// Returns `true` if some of the input is on disk.
+    pub fn is_spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Sorts everything pushed so far, and returns an iterator over it in
+    /// order.
+    pub fn finish(mut self) -> io::Result<Sorted<T>> {
+        if self.runs.is_empty() {
+            self.values.sort();
+            return Ok(Sorted {
+                inner: SortedInner::Memory(mem::take(&mut self.values).into_iter()),
+            });
+        }
+        if !self.values.is_empty() {
+            self.write_run()?;
+        }
+        let file = self.file.take().unwrap();
+        let mut runs: Vec<_> =
+            self.runs.iter().map(|&(start, end)| Records::new(start, end)).collect();
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(value) = run.next(&file)? {
+                heap.push(Reverse((value, i)));
+            }
+        }
+        Ok(Sorted { inner: SortedInner::Merge { file, runs, heap, done: false } })
+    }
+}
+
+impl<T: Encode + Ord> Default for ExternalSort<T> {
+    fn default() -> ExternalSort<T> {
+        ExternalSort::new()
+    }
+}
+
+impl<T> fmt::Debug for ExternalSort<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalSort")
+            .field("in_memory", &self.values.len())
+            .field("runs", &self.runs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The output of an [`ExternalSort`], in order.
+///
+/// Values that were spilled are decoded as they are merged. The iterator
+/// stops after the first error.
+pub struct Sorted<T> {
+    inner: SortedInner<T>,
+}
+
+enum SortedInner<T> {
+    Memory(vec::IntoIter<T>),
+    Merge {
+        file: SpillFile,
+        runs: Vec<Records>,
+        // Ties go to the earlier run, which keeps the sort stable.
+        heap: BinaryHeap<Reverse<(T, usize)>>,
+        done: bool,
+    },
+}
+
+impl<T: Encode + Ord> Iterator for Sorted<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        match &mut self.inner {
+            SortedInner::Memory(values) => values.next().map(Ok),
+            SortedInner::Merge { file, runs, heap, done } => {
+                if *done {
+                    return None;
+                }
+                let Reverse((value, i)) = heap.pop()?;
+                match runs[i].next(file) {
+                    Ok(Some(next)) => heap.push(Reverse((next, i))),
+                    Ok(None) => {}
+                    Err(e) => {
+                        *done = true;
+                        return Some(Err(e));
+                    }
+                }
+                Some(Ok(value))
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sorted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sorted").finish_non_exhaustive()
+    }
+}
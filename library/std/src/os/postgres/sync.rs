@@ -0,0 +1,442 @@
+//! Postgres-specific synchronization primitives, shared between backends.
+//!
+//! The locks in [`std::sync`] only ever see the backend they run in, since
+//! every backend is its own process. [`SharedMutex`] and [`SharedRwLock`]
+//! instead keep their value in a named dynamic shared memory segment, found
+//! through Postgres's DSM registry, and guard it with an `LWLock` in the same
+//! segment. Every backend that opens one under the same name, in the same
+//! database and as the same role, gets the same value, including parallel
+//! workers.
+//!
+//! Waiting for one of these locks can be cancelled: once the query is
+//! cancelled or the backend is asked to terminate, it unwinds with an
+//! [`Interrupted`] payload, as [`check_for_interrupts`] does, and once the
+//! statement's [`deadline`] has passed, it fails with [`TimedOut`]. Like the
+//! `LWLock`s they are built on, they don't follow `lock_timeout`.
+//!
+//! Values can be streamed from one backend to another, such as from a
+//! parallel worker to its leader, over the channels in [`shm_mq`].
+//!
+//! [`std::sync`]: crate::sync
+//! [`Interrupted`]: super::interrupt::Interrupted
+//! [`check_for_interrupts`]: super::interrupt::check_for_interrupts
+//! [`TimedOut`]: io::ErrorKind::TimedOut
+//! [`deadline`]: super::time::deadline
+
+use crate::any;
+use crate::collections::hash_map::DefaultHasher;
+use crate::fmt;
+use crate::hash::{Hash, Hasher};
+use crate::io;
+use crate::marker::PhantomData;
+use crate::mem;
+use crate::ops::{Deref, DerefMut};
+use crate::os::postgres::io::Oid;
+use crate::ptr;
+use crate::sync::atomic;
+use crate::sync::{PoisonError, RwLock};
+use crate::sys::{self, unsupported};
+use crate::time::Duration;
+
+pub mod shm_mq;
+
+/// A Postgres `LWLock`, which is only ever handled through a pointer.
+pub type LWLock = crate::ffi::c_void;
+
+/// Postgres's shared memory and lightweight locks, as used by
+/// [`SharedMutex`] and [`SharedRwLock`].
+pub trait SharedMemory: Sync {
+    /// Finds the segment called `name`, or creates it with `size` bytes,
+    /// like `GetNamedDSMSegment`, and returns where it is mapped.
+    ///
+    /// `init` must be called on a new segment before anyone else can find
+    /// it, as `GetNamedDSMSegment` does under `DSMRegistryLock`. It neither
+    /// fails nor panics.
+    ///
+    /// `name` already starts with the OIDs [`scope`](SharedMemory::scope)
+    /// returned.
+    fn attach(&self, name: &str, size: usize, init: &mut dyn FnMut(*mut u8)) -> io::Result<*mut u8>;
+
+    /// Returns the OIDs of the current database and of the role the function
+    /// runs as, `MyDatabaseId` and `GetUserId()`.
+    ///
+    /// Segment names are prefixed with both, so that functions in another
+    /// database or of another role never find the same segment.
+    fn scope(&self) -> (Oid, Oid);
+
+    /// Returns `sizeof(LWLock)`.
+    fn lwlock_size(&self) -> usize;
+
+    /// Initializes the lock at `lock`, like `LWLockInitialize`, in a tranche
+    /// called `name`.
+    fn init_lock(&self, lock: *mut LWLock, name: &str);
+
+    /// Takes the lock if it is free, like `LWLockConditionalAcquire` with
+    /// `LW_EXCLUSIVE` or `LW_SHARED`, and returns whether it did.
+    fn try_lock(&self, lock: *mut LWLock, exclusive: bool) -> bool;
+
+    /// Releases the lock, like `LWLockRelease`.
+    fn unlock(&self, lock: *mut LWLock);
+
+    /// Returns whether this backend holds the lock, like `LWLockHeldByMe`.
+    fn held_by_me(&self, lock: *mut LWLock) -> bool;
+
+    /// Waits a little before the lock is tried again, for example with
+    /// `WaitLatch(MyLatch, WL_LATCH_SET | WL_TIMEOUT | WL_EXIT_ON_PM_DEATH,
+    /// 1, ...)`.
+    ///
+    /// It should return early once the backend has a cancel or terminate
+    /// request pending, and leave it pending: the caller then asks the
+    /// installed [`Interrupts`](super::interrupt::Interrupts) and unwinds.
+    fn wait(&self) -> io::Result<()>;
+}
+
+static SHARED_MEMORY: RwLock<Option<&'static dyn SharedMemory>> = RwLock::new(None);
+
+/// Installs the implementation of [`SharedMemory`] the locks here use.
+///
+/// Until this is called, opening a lock returns an error.
+///
+/// # Safety
+///
+/// This lets code running in the backend share memory with other backends.
+/// It must only be called by the host.
+pub unsafe fn set_shared_memory(shared: &'static dyn SharedMemory) {
+    *SHARED_MEMORY.write().unwrap_or_else(PoisonError::into_inner) = Some(shared);
+}
+
+fn shared_memory() -> io::Result<&'static dyn SharedMemory> {
+    match *SHARED_MEMORY.read().unwrap_or_else(PoisonError::into_inner) {
+        Some(shared) => Ok(shared),
+        None => unsupported(),
+    }
+}
+
+/// Types that mean the same in every backend that maps them, and so can be
+/// kept in shared memory.
+///
+/// # Safety
+///
+/// The type must not hold pointers or references, which point somewhere
+/// else in each backend, nor anything that owns memory or other resources
+/// of a single backend. It must be valid at any bit pattern another backend
+/// could leave behind, which rules out `bool`, `char` and most enums, and
+/// have no `Drop` that matters: the value is never dropped.
+///
+/// Functions compiled separately must agree on the layout of the type, so a
+/// struct needs `#[repr(C)]` to implement this.
+pub unsafe trait ShmSafe {}
+
+macro_rules! shm_safe {
+    ($($t:ty)*) => {$(
+        unsafe impl ShmSafe for $t {}
+    )*};
+}
+
+shm_safe! {
+    () f32 f64
+    u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize
+    atomic::AtomicBool
+    atomic::AtomicU8 atomic::AtomicU16 atomic::AtomicU32 atomic::AtomicU64 atomic::AtomicUsize
+    atomic::AtomicI8 atomic::AtomicI16 atomic::AtomicI32 atomic::AtomicI64 atomic::AtomicIsize
+}
+
+unsafe impl<T: ShmSafe, const N: usize> ShmSafe for [T; N] {}
+
+// The start of every segment, which tells a backend that finds the segment
+// whether it holds what it expects.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Header {
+    kind: u32,
+    align: u32,
+    size: u64,
+    fingerprint: u64,
+}
+
+const MUTEX: u32 = 1;
+const RWLOCK: u32 = 2;
+
+/// Tells types apart that have the same size and alignment, such as `u32`
+/// and `f32`, so that a segment is never read as a type it doesn't hold.
+fn fingerprint<T>() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (any::type_name::<T>(), mem::size_of::<T>(), mem::align_of::<T>()).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A value and its lock in a named segment.
+struct Segment<T> {
+    backend: &'static dyn SharedMemory,
+    lock: *mut LWLock,
+    value: *mut T,
+}
+
+impl<T: ShmSafe> Segment<T> {
+    fn open(kind: u32, name: &str, init: impl FnOnce() -> T) -> io::Result<Segment<T>> {
+        let backend = shared_memory()?;
+        let lock_at = mem::size_of::<Header>().next_multiple_of(mem::align_of::<u64>());
+        let value_at = (lock_at + backend.lwlock_size()).next_multiple_of(mem::align_of::<T>());
+        let size = value_at + mem::size_of::<T>();
+        let header = Header {
+            kind,
+            align: mem::align_of::<T>() as u32,
+            size: size as u64,
+            fingerprint: fingerprint::<T>(),
+        };
+        let (database, role) = backend.scope();
+        let scoped = format!("{database}/{role}/{name}");
+
+        // The initial value is made up front, so that nothing that might
+        // panic runs while Postgres is creating the segment.
+        let mut value = Some(init());
+        let base = backend.attach(&scoped, size, &mut |base| unsafe {
+            ptr::write(base.cast::<Header>(), header);
+            backend.init_lock(base.add(lock_at).cast(), name);
+            if let Some(value) = value.take() {
+                ptr::write(base.add(value_at).cast::<T>(), value);
+            }
+        })?;
+
+        let found = unsafe { &*base.cast::<Header>() };
+        if (found.kind, found.align, found.size, found.fingerprint)
+            != (header.kind, header.align, header.size, header.fingerprint)
+            || base.addr() % mem::align_of::<T>() != 0
+        {
+            return Err(io::const_io_error!(
+                io::ErrorKind::InvalidData,
+                "named shared memory segment holds something else",
+            ));
+        }
+        unsafe {
+            Ok(Segment {
+                backend,
+                lock: base.add(lock_at).cast(),
+                value: base.add(value_at).cast(),
+            })
+        }
+    }
+
+    fn lock(&self, exclusive: bool) -> io::Result<()> {
+        if self.backend.held_by_me(self.lock) {
+            return Err(io::const_io_error!(
+                io::ErrorKind::Deadlock,
+                "shared lock is already held by this backend",
+            ));
+        }
+        while !self.backend.try_lock(self.lock, exclusive) {
+            sys::interrupt::check_now();
+            if sys::time::time_left() == Some(Duration::ZERO) {
+                return Err(io::const_io_error!(
+                    io::ErrorKind::TimedOut,
+                    "statement timeout reached while waiting for a shared lock",
+                ));
+            }
+            self.backend.wait()?;
+        }
+        Ok(())
+    }
+
+    fn try_lock(&self, exclusive: bool) -> bool {
+        !self.backend.held_by_me(self.lock) && self.backend.try_lock(self.lock, exclusive)
+    }
+}
+
+/// A mutual exclusion lock around a value in shared memory, which every
+/// backend that opens it by name can use.
+///
+/// The value is never dropped, and it isn't poisoned by a panic: a backend
+/// that panics or errors out while holding the lock releases it and leaves
+/// the value however it was.
+pub struct SharedMutex<T> {
+    segment: Segment<T>,
+}
+
+impl<T: ShmSafe> SharedMutex<T> {
+    /// Opens the mutex called `name`, creating it holding `init()` if no
+    /// backend has yet.
+    ///
+    /// `init` is called either way. Fails with
+    /// [`InvalidData`](io::ErrorKind::InvalidData) if `name` belongs to
+    /// something of another type.
+    pub fn open(name: &str, init: impl FnOnce() -> T) -> io::Result<SharedMutex<T>> {
+        Segment::open(MUTEX, name, init).map(|segment| SharedMutex { segment })
+    }
+
+    /// Takes the lock, waiting for other backends to release it if needed.
+    ///
+    /// Unwinds with an [`Interrupted`](super::interrupt::Interrupted)
+    /// payload if the query is cancelled while waiting. Fails with
+    /// [`TimedOut`](io::ErrorKind::TimedOut) if the statement's deadline
+    /// passes, and with [`Deadlock`](io::ErrorKind::Deadlock) if this
+    /// backend holds it already.
+    pub fn lock(&self) -> io::Result<SharedMutexGuard<'_, T>> {
+        self.segment.lock(true)?;
+        Ok(SharedMutexGuard { segment: &self.segment, _not_send: PhantomData })
+    }
+
+    /// Takes the lock if no backend holds it.
+    pub fn try_lock(&self) -> Option<SharedMutexGuard<'_, T>> {
+        self.segment
+            .try_lock(true)
+            .then(|| SharedMutexGuard { segment: &self.segment, _not_send: PhantomData })
+    }
+}
+
+impl<T> fmt::Debug for SharedMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedMutex").finish_non_exhaustive()
+    }
+}
+
+/// Access to the value of a [`SharedMutex`], until this is dropped.
+#[must_use = "if unused the SharedMutex will immediately unlock"]
+pub struct SharedMutexGuard<'a, T> {
+    segment: &'a Segment<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for SharedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.segment.value }
+    }
+}
+
+impl<T> DerefMut for SharedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.segment.value }
+    }
+}
+
+impl<T> Drop for SharedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.segment.backend.unlock(self.segment.lock);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A reader-writer lock around a value in shared memory, which every backend
+/// that opens it by name can use.
+///
+/// Like [`SharedMutex`], the value is never dropped and never poisoned.
+pub struct SharedRwLock<T> {
+    segment: Segment<T>,
+}
+
+impl<T: ShmSafe> SharedRwLock<T> {
+    /// Opens the lock called `name`, creating it holding `init()` if no
+    /// backend has yet.
+    ///
+    /// `init` is called either way. Fails with
+    /// [`InvalidData`](io::ErrorKind::InvalidData) if `name` belongs to
+    /// something of another type.
+    pub fn open(name: &str, init: impl FnOnce() -> T) -> io::Result<SharedRwLock<T>> {
+        Segment::open(RWLOCK, name, init).map(|segment| SharedRwLock { segment })
+    }
+
+    /// Takes the lock for reading, waiting for a writer to release it if
+    /// needed.
+    ///
+    /// Fails, or unwinds, like [`SharedMutex::lock`].
+    pub fn read(&self) -> io::Result<SharedRwLockReadGuard<'_, T>> {
+        self.segment.lock(false)?;
+        Ok(SharedRwLockReadGuard { segment: &self.segment, _not_send: PhantomData })
+    }
+
+    /// Takes the lock for writing, waiting for readers and writers to
+    /// release it if needed.
+    ///
+    /// Fails, or unwinds, like [`SharedMutex::lock`].
+    pub fn write(&self) -> io::Result<SharedRwLockWriteGuard<'_, T>> {
+        self.segment.lock(true)?;
+        Ok(SharedRwLockWriteGuard { segment: &self.segment, _not_send: PhantomData })
+    }
+
+    /// Takes the lock for reading if no writer holds it.
+    pub fn try_read(&self) -> Option<SharedRwLockReadGuard<'_, T>> {
+        self.segment
+            .try_lock(false)
+            .then(|| SharedRwLockReadGuard { segment: &self.segment, _not_send: PhantomData })
+    }
+
+    /// Takes the lock for writing if no backend holds it.
+    pub fn try_write(&self) -> Option<SharedRwLockWriteGuard<'_, T>> {
+        self.segment
+            .try_lock(true)
+            .then(|| SharedRwLockWriteGuard { segment: &self.segment, _not_send: PhantomData })
+    }
+}
+
+impl<T> fmt::Debug for SharedRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedRwLock").finish_non_exhaustive()
+    }
+}
+
+/// Shared access to the value of a [`SharedRwLock`], until this is dropped.
+#[must_use = "if unused the SharedRwLock will immediately unlock"]
+pub struct SharedRwLockReadGuard<'a, T> {
+    segment: &'a Segment<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for SharedRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.segment.value }
+    }
+}
+
+impl<T> Drop for SharedRwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.segment.backend.unlock(self.segment.lock);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedRwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive access to the value of a [`SharedRwLock`], until this is
+/// dropped.
+#[must_use = "if unused the SharedRwLock will immediately unlock"]
+pub struct SharedRwLockWriteGuard<'a, T> {
+    segment: &'a Segment<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for SharedRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.segment.value }
+    }
+}
+
+impl<T> DerefMut for SharedRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.segment.value }
+    }
+}
+
+impl<T> Drop for SharedRwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.segment.backend.unlock(self.segment.lock);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedRwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
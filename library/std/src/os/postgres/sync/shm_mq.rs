@@ -0,0 +1,348 @@
+//! Channels between backends, over Postgres's shared memory message queues.
+//!
+//! The channels in [`std::sync::mpsc`] only ever see the backend they run
+//! in, and parallel workers are backends of their own. A [`Sender`] and a
+//! [`Receiver`] attached to the same `shm_mq` can stream values from one
+//! backend to another instead, for example partial results from each worker
+//! of a parallel aggregate back to the leader. A queue has exactly one
+//! sender and one receiver, so the leader needs a queue for each worker.
+//!
+//! Which queue a name stands for is up to the host, which sets the queues
+//! up, typically in the dynamic shared memory of the parallel operation,
+//! before any worker starts.
+//!
+//! Values cross the queue encoded with [`Encode`]. Waiting for room to send,
+//! or for a message to receive, waits on the backend's latch. It unwinds
+//! with an [`Interrupted`] payload once the query is cancelled or the
+//! backend is asked to terminate, as [`check_for_interrupts`] does, and fails
+//! with [`TimedOut`] once the statement's [`deadline`] has passed. Once the
+//! other end detaches, which it does when it is dropped or its backend
+//! exits, [`send`] fails with [`Error::Disconnected`], and so does [`recv`]
+//! once every message sent before has been received.
+//!
+//! [`std::sync::mpsc`]: crate::sync::mpsc
+//! [`Encode`]: crate::os::postgres::io::Encode
+//! [`Interrupted`]: crate::os::postgres::interrupt::Interrupted
+//! [`check_for_interrupts`]: crate::os::postgres::interrupt::check_for_interrupts
+//! [`TimedOut`]: io::ErrorKind::TimedOut
+//! [`deadline`]: crate::os::postgres::time::deadline
+//! [`send`]: Sender::send
+//! [`recv`]: Receiver::recv
+
+use crate::cell::Cell;
+use crate::error;
+use crate::fmt;
+use crate::io;
+use crate::marker::PhantomData;
+use crate::os::postgres::io::Encode;
+use crate::sync::{PoisonError, RwLock};
+use crate::sys::{self, unsupported};
+use crate::time::Duration;
+
+/// A Postgres `shm_mq_handle`, which is only ever handled through a pointer.
+pub type ShmMqHandle = crate::ffi::c_void;
+
+/// What became of a send or a receive that doesn't wait, like
+/// `shm_mq_result`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The message was sent, or received, like `SHM_MQ_SUCCESS`.
+    Success,
+    /// The queue has no room, or no message, yet, like
+    /// `SHM_MQ_WOULD_BLOCK`.
+    WouldBlock,
+    /// The other end has detached, like `SHM_MQ_DETACHED`.
+    Detached,
+}
+
+/// Postgres's shared memory message queues, as used by [`Sender`] and
+/// [`Receiver`].
+pub trait MessageQueues: Sync {
+    /// Attaches this backend to the queue called `name`, as its sender if
+    /// `sender` is set and as its receiver otherwise, like
+    /// `shm_mq_set_sender` or `shm_mq_set_receiver` followed by
+    /// `shm_mq_attach`, and returns the handle.
+    ///
+    /// Fails with [`NotFound`](io::ErrorKind::NotFound) if there is no such
+    /// queue, and with [`AddrInUse`](io::ErrorKind::AddrInUse) if another
+    /// backend has attached to that end already.
+    fn attach(&self, name: &str, sender: bool) -> io::Result<*mut ShmMqHandle>;
+
+    /// Sends `message` without waiting, like `shm_mq_send` with `nowait`.
+    ///
+    /// After [`Status::WouldBlock`], the same message is passed again until
+    /// it is sent, since part of it may have been already.
+    fn send(&self, handle: *mut ShmMqHandle, message: &[u8]) -> io::Result<Status>;
+
+    /// Receives a message without waiting, like `shm_mq_receive` with
+    /// `nowait`, and appends it to `buf` on [`Status::Success`].
+    fn receive(&self, handle: *mut ShmMqHandle, buf: &mut Vec<u8>) -> io::Result<Status>;
+
+    /// Detaches from the queue, like `shm_mq_detach`.
+    fn detach(&self, handle: *mut ShmMqHandle);
+
+    /// Waits for the backend's latch to be set, or for `timeout` to pass if
+    /// there is one, and resets it, like `WaitLatch(MyLatch, WL_LATCH_SET |
+    /// WL_TIMEOUT | WL_EXIT_ON_PM_DEATH, ...)` followed by
+    /// `ResetLatch(MyLatch)`.
+    ///
+    /// It should return early once the backend has a cancel or terminate
+    /// request pending, and leave it pending: the caller then asks the
+    /// installed [`Interrupts`](crate::os::postgres::interrupt::Interrupts)
+    /// and unwinds.
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+static MESSAGE_QUEUES: RwLock<Option<&'static dyn MessageQueues>> = RwLock::new(None);
+
+/// Installs the implementation of [`MessageQueues`] that [`Sender`] and
+/// [`Receiver`] use.
+///
+/// Until this is called, attaching to a queue returns an error.
+///
+/// # Safety
+///
+/// This lets code running in the backend exchange messages with other
+/// backends. It must only be called by the host.
+pub unsafe fn set_message_queues(queues: &'static dyn MessageQueues) {
+    *MESSAGE_QUEUES.write().unwrap_or_else(PoisonError::into_inner) = Some(queues);
+}
+
+fn message_queues() -> io::Result<&'static dyn MessageQueues> {
+    match *MESSAGE_QUEUES.read().unwrap_or_else(PoisonError::into_inner) {
+        Some(queues) => Ok(queues),
+        None => unsupported(),
+    }
+}
+
+/// An error sending or receiving over a queue.
+#[derive(Debug)]
+pub enum Error {
+    /// The other end has detached, and there is nothing left to receive.
+    Disconnected,
+    /// Waiting timed out, a value could not be decoded, or the queue failed
+    /// otherwise.
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Disconnected => f.write_str("the other end of the channel has detached"),
+            Error::Io(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Disconnected => None,
+            Error::Io(error) => Some(error),
+        }
+    }
+}
+
+/// One end of a queue, detached from when this is dropped, if not before.
+struct Queue {
+    backend: &'static dyn MessageQueues,
+    handle: *mut ShmMqHandle,
+    detached: Cell<bool>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Queue {
+    fn attach(name: &str, sender: bool) -> io::Result<Queue> {
+        let backend = message_queues()?;
+        let handle = backend.attach(name, sender)?;
+        Ok(Queue { backend, handle, detached: Cell::new(false), _not_send: PhantomData })
+    }
+
+    fn detach(&self) {
+        if !self.detached.replace(true) {
+            self.backend.detach(self.handle);
+        }
+    }
+
+    fn wait(&self) -> io::Result<()> {
+        sys::interrupt::check_now();
+        let timeout = sys::time::time_left();
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::const_io_error!(
+                io::ErrorKind::TimedOut,
+                "statement timeout reached while waiting on a message queue",
+            ));
+        }
+        self.backend.wait(timeout)
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+/// The sending end of a queue.
+pub struct Sender<T> {
+    queue: Queue,
+    _marker: PhantomData<fn(&T)>,
+}
+
+impl<T: Encode> Sender<T> {
+    /// Attaches to the queue called `name` as its sender.
+    ///
+    /// Fails with [`NotFound`](io::ErrorKind::NotFound) if the host has no
+    /// such queue, and with [`AddrInUse`](io::ErrorKind::AddrInUse) if it
+    /// has a sender already.
+    pub fn attach(name: &str) -> io::Result<Sender<T>> {
+        Queue::attach(name, true).map(|queue| Sender { queue, _marker: PhantomData })
+    }
+
+    /// Sends `value`, waiting for the receiver to make room for it if
+    /// needed.
+    ///
+    /// Fails with [`Error::Disconnected`] if the receiver has detached, and
+    /// with [`TimedOut`](io::ErrorKind::TimedOut) if the statement's deadline
+    /// passes while waiting. Unwinds with an
+    /// [`Interrupted`](crate::os::postgres::interrupt::Interrupted) payload
+    /// if the query is cancelled while waiting.
+    ///
+    /// Part of the message may be in the queue by the time sending fails or
+    /// unwinds, and there is no taking it back, so the sender detaches then.
+    /// The receiver gets [`Error::Disconnected`] in place of the cut-off
+    /// message, and so does every later send.
+    pub fn send(&self, value: &T) -> Result<(), Error> {
+        if self.queue.detached.get() {
+            return Err(Error::Disconnected);
+        }
+        let mut message = Vec::new();
+        value.encode(&mut message);
+
+        // Detaches unless disarmed, including when the wait unwinds.
+        struct Sending<'a>(Option<&'a Queue>);
+        impl Drop for Sending<'_> {
+            fn drop(&mut self) {
+                if let Some(queue) = self.0 {
+                    queue.detach();
+                }
+            }
+        }
+        let mut sending = Sending(Some(&self.queue));
+        loop {
+            match self.queue.backend.send(self.queue.handle, &message)? {
+                Status::Success => {
+                    sending.0 = None;
+                    return Ok(());
+                }
+                Status::WouldBlock => self.queue.wait()?,
+                Status::Detached => return Err(Error::Disconnected),
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// The receiving end of a queue.
+pub struct Receiver<T> {
+    queue: Queue,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Encode> Receiver<T> {
+    /// Attaches to the queue called `name` as its receiver.
+    ///
+    /// Fails with [`NotFound`](io::ErrorKind::NotFound) if the host has no
+    /// such queue, and with [`AddrInUse`](io::ErrorKind::AddrInUse) if it
+    /// has a receiver already.
+    pub fn attach(name: &str) -> io::Result<Receiver<T>> {
+        Queue::attach(name, false).map(|queue| Receiver { queue, _marker: PhantomData })
+    }
+
+    /// Receives the next value, waiting for the sender to send one if
+    /// needed.
+    ///
+    /// Fails with [`Error::Disconnected`] once the sender has detached and
+    /// everything it sent has been received, and with
+    /// [`TimedOut`](io::ErrorKind::TimedOut) if the statement's deadline
+    /// passes while waiting. Unwinds with an
+    /// [`Interrupted`](crate::os::postgres::interrupt::Interrupted) payload
+    /// if the query is cancelled while waiting.
+    pub fn recv(&self) -> Result<T, Error> {
+        loop {
+            match self.try_recv()? {
+                Some(value) => return Ok(value),
+                None => self.queue.wait()?,
+            }
+        }
+    }
+
+    /// Receives the next value if one has been sent, without waiting.
+    ///
+    /// Fails like [`recv`](Receiver::recv), except that it never waits.
+    pub fn try_recv(&self) -> Result<Option<T>, Error> {
+        let mut message = Vec::new();
+        match self.queue.backend.receive(self.queue.handle, &mut message)? {
+            Status::Success => Ok(Some(T::decode(&message)?)),
+            Status::WouldBlock => Ok(None),
+            Status::Detached => Err(Error::Disconnected),
+        }
+    }
+
+    /// Returns an iterator over the values received, which waits for each
+    /// and ends once the sender has detached and everything it sent has been
+    /// received.
+    ///
+    /// Any other failure is yielded as an `Err`, after which the iterator
+    /// ends.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: Some(self) }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// An iterator over the values a [`Receiver`] receives, returned by
+/// [`Receiver::iter`].
+pub struct Iter<'a, T> {
+    receiver: Option<&'a Receiver<T>>,
+}
+
+impl<T: Encode> Iterator for Iter<'_, T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        match self.receiver?.recv() {
+            Ok(value) => Some(Ok(value)),
+            Err(Error::Disconnected) => {
+                self.receiver = None;
+                None
+            }
+            Err(Error::Io(error)) => {
+                self.receiver = None;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Iter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Iter").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,80 @@
+//! Postgres-specific time functionality.
+
+use crate::fmt;
+use crate::io;
+use crate::sys;
+use crate::sys_common::{FromInner, IntoInner};
+use crate::time::{Duration, Instant, SystemTime};
+
+/// Rounds the readings of [`Instant::now`] and [`SystemTime::now`] to
+/// multiples of `resolution`, or stops rounding them if it is zero.
+///
+/// Without `jitter`, readings are rounded down. With it, each interval of
+/// `resolution` rounds up from a secret point in it instead, so that a
+/// reading can run up to `resolution` ahead of the clock, and code can't
+/// find the edges of the intervals by spinning on the clock. Either way,
+/// [`Instant`] never goes backwards, including across changes to the
+/// resolution. A resolution over `i64::MAX` nanoseconds, some 292 years,
+/// counts as that.
+///
+/// Fails if no key for the jitter could be had from the kernel.
+///
+/// # Safety
+///
+/// Clock resolution is a defense against timing side channels between
+/// sessions. It must only be set by the host.
+///
+/// [`Instant::now`]: crate::time::Instant::now
+/// [`SystemTime::now`]: crate::time::SystemTime::now
+/// [`Instant`]: crate::time::Instant
+pub unsafe fn set_clock_resolution(resolution: Duration, jitter: bool) -> io::Result<()> {
+    sys::time::set_resolution(resolution, jitter)
+}
+
+/// Returns when `statement_timeout` will cancel the current statement, or
+/// `None` if it has no timeout.
+///
+/// This is on the clock of [`Instant::now`], so that code working through
+/// its input in chunks can compare the two and return early, with what it
+/// has so far, instead of being cancelled with nothing. The comparison is
+/// only as fine as the [clock resolution](set_clock_resolution). It is also
+/// `None` when [`Instant::now`] doesn't follow the real clock: in an
+/// `IMMUTABLE` call, and while [replaying](super::replay).
+///
+/// [`thread::sleep`] never sleeps past the real deadline either way: a sleep
+/// that would reach it sleeps until then and unwinds, with a [`TimedOut`]
+/// payload unless the host already has the cancel pending, without running
+/// the panic hook. Waiting for the locks in [`std::os::postgres::sync`]
+/// fails with [`io::ErrorKind::TimedOut`] once it has passed. `lock_timeout`
+/// is not taken into account.
+///
+/// [`thread::sleep`]: crate::thread::sleep
+/// [`std::os::postgres::sync`]: super::sync
+pub fn deadline() -> Option<Instant> {
+    sys::time::deadline().map(Instant::from_inner)
+}
+
+/// Sets the statement timeout [`deadline`] is computed from: `timeout` after
+/// `statement_start`, or none if `timeout` is `None`.
+///
+/// The host calls this at the start of every call, with
+/// `GetCurrentStatementStartTimestamp()` and the `statement_timeout` in
+/// effect, which is `None` if it is zero.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_statement_timeout(statement_start: SystemTime, timeout: Option<Duration>) {
+    sys::time::set_statement_timeout(statement_start.into_inner(), timeout)
+}
+
+/// The payload `std` unwinds with when a sleep reaches the [`deadline`]
+/// before Postgres has cancelled the statement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("statement timeout reached")
+    }
+}
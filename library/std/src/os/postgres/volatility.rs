@@ -0,0 +1,56 @@
+//! Keeping functions to the volatility they are declared with.
+//!
+//! Postgres trusts an `IMMUTABLE` function to return the same result for the
+//! same arguments, forever, and a `STABLE` one to do so within a statement.
+//! Rust code can break that without noticing, by reading the clock or by
+//! iterating a `HashMap`, whose order depends on the keys of its
+//! [`RandomState`]. The host declares the volatility of each call with
+//! [`set_volatility`], and `std` hands out values that keep to it.
+//!
+//! [`RandomState`]: crate::collections::hash_map::RandomState
+
+use crate::sys;
+use crate::sys_common::IntoInner;
+use crate::time::SystemTime;
+
+/// The volatility of a function, as in `pg_proc.provolatile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Volatility {
+    /// `VOLATILE`: everything works as usual.
+    Volatile,
+    /// `STABLE`: [`SystemTime::now`] always returns `statement_start`,
+    /// which the host takes from `GetCurrentStatementStartTimestamp`, the
+    /// same time `statement_timestamp()` returns in SQL. That is not what
+    /// `now()` returns, which is when the transaction started.
+    Stable {
+        /// When the current statement started.
+        statement_start: SystemTime,
+    },
+    /// `IMMUTABLE`: [`SystemTime::now`] always returns the
+    /// [`UNIX_EPOCH`](crate::time::UNIX_EPOCH), [`Instant::now`] always
+    /// returns the same instant, and every new [`RandomState`] gets the same
+    /// fixed keys.
+    ///
+    /// [`Instant::now`]: crate::time::Instant::now
+    /// [`RandomState`]: crate::collections::hash_map::RandomState
+    Immutable,
+}
+
+/// Declares the volatility of the current call.
+///
+/// The host calls this at the start of every call, from the function's
+/// `provolatile`, and again with [`Volatility::Volatile`] for code that runs
+/// outside of a call.
+///
+/// # Safety
+///
+/// This must only be called by the host.
+pub unsafe fn set_volatility(volatility: Volatility) {
+    sys::time::set_volatility(match volatility {
+        Volatility::Volatile => sys::time::Volatility::Volatile,
+        Volatility::Stable { statement_start } => {
+            sys::time::Volatility::Stable(statement_start.into_inner())
+        }
+        Volatility::Immutable => sys::time::Volatility::Immutable,
+    })
+}
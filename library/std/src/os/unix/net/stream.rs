@@ -92,7 +92,13 @@ impl UnixStream {
     /// ```
     #[stable(feature = "unix_socket", since = "1.10.0")]
     pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<UnixStream> {
-        super::bail_if_postgres!();
+        // Only paths the host has allowlisted through
+        // `std::os::postgres::net::set_unix_socket_allowlist` can be reached.
+        #[cfg(target_family = "postgres")]
+        {
+            Socket::connect_unix(path.as_ref()).map(UnixStream)
+        }
+        #[cfg(not(target_family = "postgres"))]
         unsafe {
             let inner = Socket::new_raw(libc::AF_UNIX, libc::SOCK_STREAM)?;
             let (addr, len) = sockaddr_un(path.as_ref())?;
@@ -183,7 +189,6 @@ impl UnixStream {
     /// ```
     #[stable(feature = "unix_socket", since = "1.10.0")]
     pub fn try_clone(&self) -> io::Result<UnixStream> {
-        super::bail_if_postgres!();
         self.0.duplicate().map(UnixStream)
     }
 
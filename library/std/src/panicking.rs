@@ -291,8 +291,72 @@ fn default_hook(info: &PanicInfo<'_>) {
     }
 }
 
+// The hook the host installs runs for every panic, before whichever hook the
+// call has registered, so that the call can't take it out of the chain. See
+// `std::os::postgres::panic`.
 #[cfg(target_family = "postgres")]
-fn default_hook(_: &PanicInfo<'_>) {}
+static HOST_HOOK: RwLock<Option<Box<dyn Fn(&PanicInfo<'_>) + 'static + Sync + Send>>> =
+    RwLock::new(None);
+
+#[cfg(target_family = "postgres")]
+pub(crate) fn set_host_hook(hook: Option<Box<dyn Fn(&PanicInfo<'_>) + 'static + Sync + Send>>) {
+    let mut host_hook = HOST_HOOK.write().unwrap_or_else(PoisonError::into_inner);
+    let old = mem::replace(&mut *host_hook, hook);
+    drop(host_hook);
+    drop(old);
+}
+
+#[cfg(target_family = "postgres")]
+fn host_hook(info: &PanicInfo<'_>) {
+    if let Some(hook) = &*HOST_HOOK.read().unwrap_or_else(PoisonError::into_inner) {
+        hook(info);
+    }
+}
+
+// There is no standard error to print to, so the default hook does nothing.
+// The message goes to the call's output capture whichever hook runs.
+#[cfg(target_family = "postgres")]
+fn default_hook(_info: &PanicInfo<'_>) {}
+
+// Writes the message to the call's output capture, if it has one, for every
+// panic. See `std::os::postgres::io::start_capture`.
+#[cfg(target_family = "postgres")]
+fn capture_panic(info: &PanicInfo<'_>) {
+    if let Some(local) = set_output_capture(None) {
+        let location = info.location().unwrap();
+        let msg = match info.payload().downcast_ref::<&'static str>() {
+            Some(s) => *s,
+            None => match info.payload().downcast_ref::<String>() {
+                Some(s) => &s[..],
+                None => "Box<dyn Any>",
+            },
+        };
+        crate::sys::stdio::write_capture(
+            &mut local.lock().unwrap_or_else(|e| e.into_inner()),
+            format_args!("panicked at '{msg}', {location}\n"),
+        );
+        set_output_capture(Some(local));
+    }
+}
+
+/// Runs `f` with the default hook registered, and registers the hook that
+/// was registered before once `f` returns or unwinds, whatever `f` did to it.
+#[cfg(target_family = "postgres")]
+pub(crate) fn with_scoped_hook<R>(f: impl FnOnce() -> R) -> R {
+    struct Restore(Hook);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let mut hook = HOOK.write().unwrap_or_else(PoisonError::into_inner);
+            let scoped = mem::replace(&mut *hook, mem::take(&mut self.0));
+            drop(hook);
+            drop(scoped);
+        }
+    }
+
+    let _restore = Restore(mem::take(&mut *HOOK.write().unwrap_or_else(PoisonError::into_inner)));
+    f()
+}
 
 #[cfg(not(test))]
 #[doc(hidden)]
@@ -356,7 +420,8 @@ pub mod panic_count {
 
         LOCAL_PANIC_COUNT.with(|c| {
             let (count, in_panic_hook) = c.get();
-            if in_panic_hook {
+            // On postgres, the hook runs under `catch_unwind` instead.
+            if in_panic_hook && cfg!(not(target_family = "postgres")) {
                 return Some(MustAbort::PanicInHook);
             }
             c.set((count + 1, run_panic_hook));
@@ -364,6 +429,11 @@ pub mod panic_count {
         })
     }
 
+    #[cfg(target_family = "postgres")]
+    pub fn in_panic_hook() -> bool {
+        LOCAL_PANIC_COUNT.with(|c| c.get().1)
+    }
+
     pub fn finished_panic_hook() {
         LOCAL_PANIC_COUNT.with(|c| {
             let (count, _) = c.get();
@@ -675,6 +745,14 @@ fn rust_panic_with_hook(
     location: &Location<'_>,
     can_unwind: bool,
 ) -> ! {
+    // A panic in the hook runs no hook of its own, and is caught on its way
+    // out of the hook, so that the backend isn't aborted for it.
+    #[cfg(target_family = "postgres")]
+    if can_unwind && panic_count::in_panic_hook() {
+        panic_count::increase(true);
+        rust_panic(payload)
+    }
+
     let must_abort = panic_count::increase(true);
 
     // Check if we need to abort immediately.
@@ -696,6 +774,12 @@ fn rust_panic_with_hook(
     }
 
     let mut info = PanicInfo::internal_constructor(message, location, can_unwind);
+    #[cfg(target_family = "postgres")]
+    {
+        info.set_payload(payload.get());
+        run_hook(&host_hook, &info);
+        run_hook(&capture_panic, &info);
+    }
     let hook = HOOK.read().unwrap_or_else(PoisonError::into_inner);
     match *hook {
         // Some platforms (like wasm) know that printing to stderr won't ever actually
@@ -704,14 +788,17 @@ fn rust_panic_with_hook(
         // methods, this means we avoid formatting the string at all!
         // (The panic runtime might still call `payload.take_box()` though and trigger
         // formatting.)
+        #[cfg(not(target_family = "postgres"))]
         Hook::Default if panic_output().is_none() => {}
         Hook::Default => {
+            #[cfg(not(target_family = "postgres"))]
             info.set_payload(payload.get());
-            default_hook(&info);
+            run_hook(&default_hook, &info);
         }
         Hook::Custom(ref hook) => {
+            #[cfg(not(target_family = "postgres"))]
             info.set_payload(payload.get());
-            hook(&info);
+            run_hook(hook, &info);
         }
     };
     drop(hook);
@@ -732,6 +819,19 @@ fn rust_panic_with_hook(
     rust_panic(payload)
 }
 
+#[cfg(not(target_family = "postgres"))]
+fn run_hook(hook: &(dyn Fn(&PanicInfo<'_>) + Sync + Send), info: &PanicInfo<'_>) {
+    hook(info)
+}
+
+// A hook that panics is cut short, and the panic it was called for goes on.
+#[cfg(target_family = "postgres")]
+fn run_hook(hook: &(dyn Fn(&PanicInfo<'_>) + Sync + Send), info: &PanicInfo<'_>) {
+    // SAFETY: as in `catch_unwind`. What the hook panicked with is dropped
+    // right away.
+    drop(unsafe { r#try(|| hook(info)) });
+}
+
 /// This is the entry point for `resume_unwind`.
 /// It just forwards the payload to the panic runtime.
 pub fn rust_panic_without_hook(payload: Box<dyn Any + Send>) -> ! {
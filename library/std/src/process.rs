@@ -2120,6 +2120,9 @@ impl Child {
 /// ```
 #[stable(feature = "rust1", since = "1.0.0")]
 pub fn exit(code: i32) -> ! {
+    // On postgres this only ends the call, and the backend's stdout must stay
+    // buffered for the calls after it.
+    #[cfg(not(target_family = "postgres"))]
     crate::rt::cleanup();
     crate::sys::os::exit(code)
 }
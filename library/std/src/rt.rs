@@ -109,6 +109,8 @@ unsafe fn init(argc: isize, argv: *const *const u8, sigpipe: u8) {
 // One-time runtime cleanup.
 // Runs after `main` or at program exit.
 // NOTE: this is not guaranteed to run, for example when the program aborts.
+// On postgres, `process::exit` doesn't run it, and tests leave out `main`.
+#[cfg_attr(all(test, target_family = "postgres"), allow(dead_code))]
 pub(crate) fn cleanup() {
     static CLEANUP: Once = Once::new();
     CLEANUP.call_once(|| unsafe {
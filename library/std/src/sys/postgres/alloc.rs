@@ -1,55 +1,292 @@
+#[cfg(test)]
+mod tests;
+
 use crate::alloc::{GlobalAlloc, Layout, System};
+use crate::cmp;
+use crate::intrinsics;
+use crate::os::postgres::alloc::OutOfMemory;
 #[allow(unused)]
 use crate::ptr;
-use crate::sys::common::alloc::{realloc_fallback, MIN_ALIGN};
+use crate::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering::Relaxed};
+use crate::sys::common::alloc::MIN_ALIGN;
+
+mod debug;
+
+pub use debug::check_leaks;
+
+// Functions of different roles share the backend's heap, so memory one of them
+// frees can turn up, uninitialized, in another's next allocation. When the
+// host asks for it, freed memory is overwritten first: `SCRUB_ON` plus the
+// byte to fill it with, or zero for off.
+static SCRUB: AtomicU16 = AtomicU16::new(0);
+const SCRUB_ON: u16 = 0x100;
+
+pub fn set_scrub(fill: Option<u8>) {
+    SCRUB.store(fill.map_or(0, |fill| SCRUB_ON | fill as u16), Relaxed);
+}
+
+#[inline]
+fn scrub_fill() -> Option<u8> {
+    let scrub = SCRUB.load(Relaxed);
+    if scrub & SCRUB_ON != 0 { Some(scrub as u8) } else { None }
+}
+
+// In debug mode, every allocation is recorded until it is freed, so that the
+// host can report the ones a call leaves behind.
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+// What freed memory is overwritten with in debug mode, unless it is scrubbed
+// with something else: the byte Postgres's own `CLOBBER_FREED_MEMORY` uses.
+const POISON: u8 = 0x7f;
+
+pub fn set_debug(enabled: bool) {
+    DEBUG.store(enabled, Relaxed);
+}
+
+/// Hands every allocation still live since the last call to `f`, with its
+/// size, alignment and allocation site, and forgets them.
+pub fn take_leaks(f: impl FnMut(usize, usize, &[usize])) {
+    debug::take(f)
+}
+
+#[inline]
+fn free_fill() -> Option<u8> {
+    match scrub_fill() {
+        None if DEBUG.load(Relaxed) => Some(POISON),
+        fill => fill,
+    }
+}
+
+// The host can cap how much memory a call allocates. `CALL_BYTES` is what it
+// has allocated so far, net of what it freed, and `BUDGET` the cap, which is
+// `usize::MAX` for none.
+static BUDGET: AtomicUsize = AtomicUsize::new(usize::MAX);
+static CALL_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_budget(budget: Option<usize>) {
+    BUDGET.store(budget.unwrap_or(usize::MAX), Relaxed);
+    CALL_BYTES.store(0, Relaxed);
+}
+
+/// Counts `size` more bytes against the budget, or returns `false` if that
+/// would go over it.
+#[inline]
+fn charge(size: usize) -> bool {
+    match CALL_BYTES.load(Relaxed).checked_add(size) {
+        Some(used) if used <= BUDGET.load(Relaxed) => {
+            CALL_BYTES.store(used, Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Unwinds out of the call with an `OutOfMemory` payload, once the alloc
+/// error hook has run. See `std::os::postgres::alloc::set_budget`.
+pub fn out_of_memory(layout: Layout) -> ! {
+    // Unwinding allocates too. The host sets a new budget for the next call.
+    BUDGET.store(usize::MAX, Relaxed);
+    crate::panic::resume_unwind(Box::new(OutOfMemory::new(layout.size())))
+}
+
+#[inline]
+fn refund(size: usize) {
+    // Memory allocated before the count was reset may be freed after it.
+    CALL_BYTES.store(CALL_BYTES.load(Relaxed).saturating_sub(size), Relaxed);
+}
+
+// Counters for `stats`, kept the same way. `LIVE_BYTES` is what is
+// allocated right now; the rest count from the last `reset_stats`.
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static REALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static LARGEST: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Stats {
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+    pub allocations: u64,
+    pub reallocations: u64,
+    pub largest: usize,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        live_bytes: LIVE_BYTES.load(Relaxed),
+        peak_bytes: PEAK_BYTES.load(Relaxed),
+        allocations: ALLOCATIONS.load(Relaxed),
+        reallocations: REALLOCATIONS.load(Relaxed),
+        largest: LARGEST.load(Relaxed),
+    }
+}
+
+pub fn reset_stats() {
+    PEAK_BYTES.store(LIVE_BYTES.load(Relaxed), Relaxed);
+    ALLOCATIONS.store(0, Relaxed);
+    REALLOCATIONS.store(0, Relaxed);
+    LARGEST.store(0, Relaxed);
+}
+
+#[inline]
+fn count_resize(old_size: usize, new_size: usize) {
+    let live = LIVE_BYTES.load(Relaxed).saturating_sub(old_size).saturating_add(new_size);
+    LIVE_BYTES.store(live, Relaxed);
+    PEAK_BYTES.store(PEAK_BYTES.load(Relaxed).max(live), Relaxed);
+    LARGEST.store(LARGEST.load(Relaxed).max(new_size), Relaxed);
+}
+
+// Code that grows collections or builds strings for long allocates all the
+// while, so the allocator asks for pending interrupts, every few hundred
+// allocations. It must not unwind, so once one is pending it fails the
+// allocation instead, and `rust_oom` unwinds with `Interrupted` for it.
+// Shrinking never fails for it.
+#[inline]
+fn interrupted() -> bool {
+    crate::sys::interrupt::poll()
+}
 
 #[stable(feature = "alloc_system_type", since = "1.28.0")]
 unsafe impl GlobalAlloc for System {
     #[inline]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // jemalloc provides alignment less than MIN_ALIGN for small allocations.
-        // So only rely on MIN_ALIGN if size >= align.
-        // Also see <https://github.com/rust-lang/rust/issues/45955> and
-        // <https://github.com/rust-lang/rust/issues/62251#issuecomment-507580914>.
-        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
-            libc::malloc(layout.size()) as *mut u8
-        } else {
-            #[cfg(target_os = "macos")]
-            {
-                if layout.align() > (1 << 31) {
-                    return ptr::null_mut();
-                }
-            }
-            aligned_malloc(&layout)
+        if interrupted() {
+            return ptr::null_mut();
         }
+        let ptr = allocate(layout, false);
+        if !ptr.is_null() {
+            ALLOCATIONS.store(ALLOCATIONS.load(Relaxed) + 1, Relaxed);
+            count_resize(0, layout.size());
+        }
+        ptr
     }
 
     #[inline]
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        // See the comment above in `alloc` for why this check looks the way it does.
-        if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
-            libc::calloc(layout.size(), 1) as *mut u8
-        } else {
-            let ptr = self.alloc(layout);
-            if !ptr.is_null() {
-                ptr::write_bytes(ptr, 0, layout.size());
-            }
-            ptr
+        if interrupted() {
+            return ptr::null_mut();
         }
+        let ptr = allocate(layout, true);
+        if !ptr.is_null() {
+            ALLOCATIONS.store(ALLOCATIONS.load(Relaxed) + 1, Relaxed);
+            count_resize(0, layout.size());
+        }
+        ptr
     }
 
     #[inline]
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        libc::free(ptr as *mut libc::c_void)
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.store(LIVE_BYTES.load(Relaxed).saturating_sub(layout.size()), Relaxed);
+        free(ptr, layout)
     }
 
     #[inline]
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        if layout.align() <= MIN_ALIGN && layout.align() <= new_size {
-            libc::realloc(ptr as *mut libc::c_void, new_size) as *mut u8
-        } else {
-            realloc_fallback(self, ptr, layout, new_size)
+        if new_size > layout.size() && interrupted() {
+            return ptr::null_mut();
+        }
+        let new = reallocate(ptr, layout, new_size);
+        if !new.is_null() {
+            REALLOCATIONS.store(REALLOCATIONS.load(Relaxed) + 1, Relaxed);
+            count_resize(layout.size(), new_size);
+        }
+        new
+    }
+}
+
+// What follows does the work of `GlobalAlloc` for `System`, less the
+// counting for `stats`, so that a `realloc` that moves the block counts as
+// neither an allocation nor a free.
+
+#[inline]
+unsafe fn allocate(layout: Layout, zeroed: bool) -> *mut u8 {
+    if !charge(layout.size()) {
+        return ptr::null_mut();
+    }
+    let ptr = if zeroed { raw_alloc_zeroed(layout) } else { raw_alloc(layout) };
+    if ptr.is_null() {
+        refund(layout.size());
+    } else if DEBUG.load(Relaxed) {
+        debug::unpoison(ptr, layout.size(), zeroed);
+        debug::track(ptr, layout.size(), layout.align());
+    }
+    ptr
+}
+
+#[inline]
+unsafe fn free(ptr: *mut u8, layout: Layout) {
+    refund(layout.size());
+    let debug = DEBUG.load(Relaxed);
+    if debug {
+        debug::untrack(ptr);
+    }
+    if let Some(fill) = free_fill() {
+        // Volatile, so that the writes aren't optimized away as dead
+        // stores to memory that is about to be freed.
+        intrinsics::volatile_set_memory(ptr, fill, layout.size());
+    }
+    if debug {
+        debug::poison(ptr, layout.size());
+    }
+    libc::free(ptr as *mut libc::c_void)
+}
+
+#[inline]
+unsafe fn reallocate(ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    if free_fill().is_none() && layout.align() <= MIN_ALIGN && layout.align() <= new_size {
+        let grows = new_size > layout.size();
+        if grows && !charge(new_size - layout.size()) {
+            return ptr::null_mut();
+        }
+        let new = libc::realloc(ptr as *mut libc::c_void, new_size) as *mut u8;
+        if new.is_null() == grows {
+            // Either the growth failed, or the shrink succeeded.
+            refund(layout.size().abs_diff(new_size));
+        }
+        return new;
+    }
+    // When freed memory is overwritten, the block is always moved by hand:
+    // `realloc(3)` may move it and free the old one, or give back the tail
+    // of a shrinking one, without us seeing either.
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let new = allocate(new_layout, false);
+    if !new.is_null() {
+        ptr::copy_nonoverlapping(ptr, new, cmp::min(layout.size(), new_size));
+        free(ptr, layout);
+    }
+    new
+}
+
+#[inline]
+unsafe fn raw_alloc(layout: Layout) -> *mut u8 {
+    // jemalloc provides alignment less than MIN_ALIGN for small allocations.
+    // So only rely on MIN_ALIGN if size >= align.
+    // Also see <https://github.com/rust-lang/rust/issues/45955> and
+    // <https://github.com/rust-lang/rust/issues/62251#issuecomment-507580914>.
+    if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+        libc::malloc(layout.size()) as *mut u8
+    } else {
+        #[cfg(target_os = "macos")]
+        {
+            if layout.align() > (1 << 31) {
+                return ptr::null_mut();
+            }
+        }
+        aligned_malloc(&layout)
+    }
+}
+
+#[inline]
+unsafe fn raw_alloc_zeroed(layout: Layout) -> *mut u8 {
+    // See the comment above in `raw_alloc` for why this check looks the way it does.
+    if layout.align() <= MIN_ALIGN && layout.align() <= layout.size() {
+        libc::calloc(layout.size(), 1) as *mut u8
+    } else {
+        let ptr = raw_alloc(layout);
+        if !ptr.is_null() {
+            ptr::write_bytes(ptr, 0, layout.size());
         }
+        ptr
     }
 }
 
@@ -0,0 +1,287 @@
+//! The allocator's debug mode: a record of every live allocation, so that the
+//! ones a call leaves behind can be reported when it ends.
+//!
+//! The records are kept in a hash table allocated straight from `malloc(3)`,
+//! not through `System`, so that keeping them never allocates a record of its
+//! own and the allocator is never entered twice.
+
+use crate::ffi::c_void;
+use crate::mem;
+use crate::ptr;
+use crate::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use crate::sync::{Mutex, PoisonError};
+
+/// How many return addresses are kept for each allocation.
+pub const FRAMES: usize = 12;
+
+#[derive(Clone, Copy)]
+struct Record {
+    /// The allocation's address, or zero for an empty slot.
+    ptr: usize,
+    size: usize,
+    align: usize,
+    frames: [usize; FRAMES],
+}
+
+struct Table {
+    slots: *mut Record,
+    /// The number of slots minus one; the number is a power of two.
+    mask: usize,
+    len: usize,
+}
+
+unsafe impl Send for Table {}
+
+static TABLE: Mutex<Table> = Mutex::new(Table { slots: ptr::null_mut(), mask: 0, len: 0 });
+
+// Set while the allocation site is being unwound, in case the unwinder
+// allocates, so that it doesn't try to unwind itself.
+static TRACING: AtomicBool = AtomicBool::new(false);
+
+impl Table {
+    fn home(&self, ptr: usize) -> usize {
+        ((ptr as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize & self.mask
+    }
+
+    unsafe fn slot(&self, i: usize) -> &mut Record {
+        &mut *self.slots.add(i)
+    }
+
+    unsafe fn insert(&mut self, record: Record) {
+        if self.slots.is_null() || (self.len + 1) * 4 > (self.mask + 1) * 3 {
+            if !self.grow() {
+                // Out of memory for the table itself: the allocation goes
+                // untracked, which beats failing it.
+                return;
+            }
+        }
+        let mut i = self.home(record.ptr);
+        while self.slot(i).ptr != 0 {
+            i = (i + 1) & self.mask;
+        }
+        *self.slot(i) = record;
+        self.len += 1;
+    }
+
+    unsafe fn grow(&mut self) -> bool {
+        let cap = if self.slots.is_null() { 1024 } else { (self.mask + 1) * 2 };
+        let slots = libc::calloc(cap, mem::size_of::<Record>()) as *mut Record;
+        if slots.is_null() {
+            return false;
+        }
+        let old = mem::replace(self, Table { slots, mask: cap - 1, len: 0 });
+        if !old.slots.is_null() {
+            for i in 0..=old.mask {
+                let record = *old.slot(i);
+                if record.ptr != 0 {
+                    self.insert(record);
+                }
+            }
+            libc::free(old.slots as *mut c_void);
+        }
+        true
+    }
+
+    unsafe fn remove(&mut self, ptr: usize) {
+        if self.slots.is_null() {
+            return;
+        }
+        let mut i = self.home(ptr);
+        loop {
+            match self.slot(i).ptr {
+                0 => return,
+                p if p == ptr => break,
+                _ => i = (i + 1) & self.mask,
+            }
+        }
+        self.len -= 1;
+        // Shift later records of the same run back, so that lookups never
+        // stop early at the hole this leaves.
+        let mut j = i;
+        loop {
+            j = (j + 1) & self.mask;
+            let record = *self.slot(j);
+            if record.ptr == 0 {
+                break;
+            }
+            let home = self.home(record.ptr);
+            let stays = if i <= j { i < home && home <= j } else { i < home || home <= j };
+            if !stays {
+                *self.slot(i) = record;
+                i = j;
+            }
+        }
+        self.slot(i).ptr = 0;
+    }
+}
+
+/// Records a new allocation.
+pub unsafe fn track(ptr: *mut u8, size: usize, align: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    let frames = if TRACING.swap(true, Relaxed) {
+        [0; FRAMES]
+    } else {
+        let frames = trace();
+        TRACING.store(false, Relaxed);
+        frames
+    };
+    let record = Record { ptr: ptr.addr(), size, align, frames };
+    TABLE.lock().unwrap_or_else(PoisonError::into_inner).insert(record);
+}
+
+/// Forgets an allocation that is about to be freed.
+pub unsafe fn untrack(ptr: *mut u8) {
+    TABLE.lock().unwrap_or_else(PoisonError::into_inner).remove(ptr.addr());
+}
+
+/// Hands every allocation recorded so far to `f`, with its size, alignment
+/// and the return addresses of its allocation site, and forgets them all.
+pub fn take(mut f: impl FnMut(usize, usize, &[usize])) {
+    // The table is swapped out before `f` runs, since `f` may well allocate.
+    let table = mem::replace(
+        &mut *TABLE.lock().unwrap_or_else(PoisonError::into_inner),
+        Table { slots: ptr::null_mut(), mask: 0, len: 0 },
+    );
+    if table.slots.is_null() {
+        return;
+    }
+    unsafe {
+        for i in 0..=table.mask {
+            let record = *table.slot(i);
+            if record.ptr != 0 {
+                let depth = record.frames.iter().position(|&ip| ip == 0).unwrap_or(FRAMES);
+                f(record.size, record.align, &record.frames[..depth]);
+            }
+        }
+        libc::free(table.slots as *mut c_void);
+    }
+}
+
+/// Returns the addresses the current call stack returns to, innermost first.
+///
+/// This comes up empty if there are no unwind tables to go by.
+#[inline(never)]
+fn trace() -> [usize; FRAMES] {
+    use unwind as uw;
+
+    struct Trace {
+        frames: [usize; FRAMES],
+        depth: usize,
+        // This function's own frame, and the allocator's.
+        skip: usize,
+    }
+
+    extern "C" fn frame(
+        ctx: *mut uw::_Unwind_Context,
+        arg: *mut c_void,
+    ) -> uw::_Unwind_Reason_Code {
+        let trace = unsafe { &mut *(arg as *mut Trace) };
+        if trace.skip > 0 {
+            trace.skip -= 1;
+            return uw::_URC_NO_REASON;
+        }
+        let ip = unsafe { uw::_Unwind_GetIP(ctx) } as usize;
+        if ip == 0 {
+            return uw::_URC_END_OF_STACK;
+        }
+        trace.frames[trace.depth] = ip;
+        trace.depth += 1;
+        if trace.depth == FRAMES { uw::_URC_END_OF_STACK } else { uw::_URC_NO_REASON }
+    }
+
+    let mut trace = Trace { frames: [0; FRAMES], depth: 0, skip: 2 };
+    unsafe { uw::_Unwind_Backtrace(frame, &mut trace as *mut Trace as *mut c_void) };
+    trace.frames
+}
+
+/// Asks whichever leak checker the backend runs under to check for leaks
+/// now: LeakSanitizer, on its own or as part of AddressSanitizer, or
+/// Valgrind's Memcheck. Both report what they find themselves.
+pub fn check_leaks() {
+    use crate::sys::weak::weak;
+
+    weak!(fn __lsan_do_recoverable_leak_check() -> libc::c_int);
+
+    if let Some(check) = __lsan_do_recoverable_leak_check.get() {
+        unsafe { check() };
+    }
+    // `VALGRIND_DO_ADDED_LEAK_CHECK`, which only reports leaks that are new
+    // since the last check.
+    valgrind_request(0x4d43_0006, [0, 1, 0, 0, 0]);
+}
+
+/// Tells whichever memory checker the backend runs under, AddressSanitizer or
+/// Valgrind's Memcheck, that the `size` bytes at `ptr` are about to be freed,
+/// so that any access to them from now on is reported.
+pub unsafe fn poison(ptr: *mut u8, size: usize) {
+    use crate::sys::weak::weak;
+
+    weak!(fn __asan_poison_memory_region(*const c_void, usize) -> ());
+
+    if let Some(poison) = __asan_poison_memory_region.get() {
+        poison(ptr.cast(), size);
+    }
+    // `VALGRIND_MAKE_MEM_NOACCESS`.
+    valgrind_request(0x4d43_0000, [ptr.addr(), size, 0, 0, 0]);
+}
+
+/// Undoes `poison` for the `size` bytes at `ptr`, which were just allocated:
+/// they may be accessed again, and read once they are written, or right away
+/// if they were `zeroed`.
+pub unsafe fn unpoison(ptr: *mut u8, size: usize, zeroed: bool) {
+    use crate::sys::weak::weak;
+
+    weak!(fn __asan_unpoison_memory_region(*const c_void, usize) -> ());
+
+    if let Some(unpoison) = __asan_unpoison_memory_region.get() {
+        unpoison(ptr.cast(), size);
+    }
+    // `VALGRIND_MAKE_MEM_DEFINED` or `VALGRIND_MAKE_MEM_UNDEFINED`.
+    let request = if zeroed { 0x4d43_0002 } else { 0x4d43_0001 };
+    valgrind_request(request, [ptr.addr(), size, 0, 0, 0]);
+}
+
+/// Makes a Valgrind client request, as `VALGRIND_DO_CLIENT_REQUEST_EXPR`
+/// does, and returns its result, or 0 if the backend is not running under
+/// Valgrind.
+///
+/// Outside Valgrind the magic sequence is a few rotates that cancel out.
+fn valgrind_request(request: usize, args: [usize; 5]) -> usize {
+    let args = [request, args[0], args[1], args[2], args[3], args[4]];
+    let mut result = 0usize;
+    cfg_if::cfg_if! {
+        if #[cfg(all(target_os = "linux", target_arch = "x86_64"))] {
+            unsafe {
+                crate::arch::asm!(
+                    "rol rdi, 3",
+                    "rol rdi, 13",
+                    "rol rdi, 61",
+                    "rol rdi, 51",
+                    "xchg rbx, rbx",
+                    inout("rdx") result,
+                    in("rax") args.as_ptr(),
+                    options(nostack),
+                );
+            }
+        } else if #[cfg(all(target_os = "linux", target_arch = "aarch64"))] {
+            unsafe {
+                crate::arch::asm!(
+                    "ror x12, x12, #3",
+                    "ror x12, x12, #13",
+                    "ror x12, x12, #51",
+                    "ror x12, x12, #61",
+                    "orr x10, x10, x10",
+                    inout("x3") result,
+                    in("x4") args.as_ptr(),
+                    options(nostack),
+                );
+            }
+        } else {
+            // Valgrind doesn't run here.
+            let _ = args;
+        }
+    }
+    result
+}
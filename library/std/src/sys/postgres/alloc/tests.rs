@@ -0,0 +1,63 @@
+use super::{charge, out_of_memory, refund, set_budget, CALL_BYTES};
+use crate::alloc::{GlobalAlloc, Layout, System};
+use crate::os::postgres::alloc::OutOfMemory;
+use crate::os::postgres::interrupt::{set_interrupts, Interrupts};
+use crate::panic;
+use crate::sync::atomic::Ordering::Relaxed;
+
+// The budget is global, so a single test sets it and lifts it again.
+#[test]
+fn budget() {
+    set_budget(Some(100));
+    assert!(charge(60));
+    assert!(!charge(41));
+
+    // Freeing memory allocated before the call makes room, but the count
+    // never goes below zero.
+    refund(1000);
+    assert_eq!(CALL_BYTES.load(Relaxed), 0);
+    assert!(charge(100));
+    assert!(!charge(1));
+
+    // Failing unwinds with `OutOfMemory`, and lifts the budget for the rest
+    // of the call.
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let payload = panic::catch_unwind(|| out_of_memory(layout)).unwrap_err();
+    assert_eq!(payload.downcast_ref::<OutOfMemory>().map(OutOfMemory::size), Some(64));
+    assert!(charge(1000));
+
+    set_budget(None);
+}
+
+// So are the interrupts.
+#[test]
+fn interrupted() {
+    struct Pending;
+    impl Interrupts for Pending {
+        fn pending(&self) -> bool {
+            true
+        }
+    }
+
+    // Once one is pending, some allocation within the next few hundred fails,
+    // but shrinking never does.
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut block = unsafe { System.alloc(layout) };
+    assert!(!block.is_null());
+    unsafe { set_interrupts(Some(&Pending)) };
+    let failed = (0..1000).any(|_| unsafe {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            System.dealloc(ptr, layout);
+        }
+        ptr.is_null()
+    });
+    let shrunk = (0..1000).all(|_| {
+        block = unsafe { System.realloc(block, layout, 64) };
+        !block.is_null()
+    });
+    unsafe { set_interrupts(None) };
+    assert!(failed);
+    assert!(shrunk);
+    unsafe { System.dealloc(block, layout) };
+}
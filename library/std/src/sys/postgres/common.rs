@@ -1,4 +1,5 @@
 use crate::io as std_io;
+use crate::os::postgres::replay::Value;
 use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use std_io::ErrorKind;
 pub mod memchr {
@@ -97,7 +98,25 @@ pub fn abort_internal() -> ! {
     core::intrinsics::abort();
 }
 
+// FIXME: Can this lead to HashDOS?
+const FIXED_KEYS: (u64, u64) = (1, 2);
+
 pub fn hashmap_random_keys() -> (u64, u64) {
-    // FIXME: Can this lead to HashDOS?
-    (1, 2)
+    crate::sys::replay::observe(
+        || FIXED_KEYS,
+        |&(k0, k1)| Value::HashKeys(k0, k1),
+        |value| match value {
+            Value::HashKeys(k0, k1) => Ok((k0, k1)),
+            value => Err(value),
+        },
+    )
+}
+
+/// Returns the keys every `RandomState` gets while the call is declared
+/// immutable, or `None` when they may vary.
+pub fn fixed_hashmap_keys() -> Option<(u64, u64)> {
+    match crate::sys::time::volatility() {
+        crate::sys::time::Volatility::Immutable => Some(FIXED_KEYS),
+        _ => None,
+    }
 }
@@ -6,8 +6,11 @@ use crate::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, Owned
 use crate::sys::unsupported;
 use crate::sys_common::{AsInner, FromInner, IntoInner};
 
+// The only descriptors that exist on this platform are the ones the host lets
+// code reach (see `net::Socket::connect_unix`), and plain I/O through a
+// `FileDesc` stays unsupported; the socket code does its own syscalls.
 #[derive(Debug)]
-pub struct FileDesc(!);
+pub struct FileDesc(OwnedFd);
 
 impl FileDesc {
     pub fn read(&self, _: &mut [u8]) -> io::Result<usize> {
@@ -63,7 +66,7 @@ impl FileDesc {
     }
 
     pub fn duplicate(&self) -> io::Result<FileDesc> {
-        unsupported()
+        Ok(Self(self.0.try_clone()?))
     }
 }
 
@@ -74,43 +77,45 @@ impl<'a> Read for &'a FileDesc {
 }
 
 impl AsInner<OwnedFd> for FileDesc {
+    #[inline]
     fn as_inner(&self) -> &OwnedFd {
-        unimplemented!()
+        &self.0
     }
 }
 
 impl IntoInner<OwnedFd> for FileDesc {
     fn into_inner(self) -> OwnedFd {
-        unimplemented!()
+        self.0
     }
 }
 
 impl FromInner<OwnedFd> for FileDesc {
     fn from_inner(owned_fd: OwnedFd) -> Self {
-        unimplemented!()
+        Self(owned_fd)
     }
 }
 
 impl AsFd for FileDesc {
     fn as_fd(&self) -> BorrowedFd<'_> {
-        unimplemented!()
+        self.0.as_fd()
     }
 }
 
 impl AsRawFd for FileDesc {
+    #[inline]
     fn as_raw_fd(&self) -> RawFd {
-        unimplemented!()
+        self.0.as_raw_fd()
     }
 }
 
 impl IntoRawFd for FileDesc {
     fn into_raw_fd(self) -> RawFd {
-        unimplemented!()
+        self.0.into_raw_fd()
     }
 }
 
 impl FromRawFd for FileDesc {
     unsafe fn from_raw_fd(raw_fd: RawFd) -> Self {
-        unimplemented!()
+        Self(FromRawFd::from_raw_fd(raw_fd))
     }
 }
@@ -2,25 +2,41 @@ use crate::fmt;
 use crate::hash::{Hash, Hasher};
 use crate::path::{Path, PathBuf};
 use crate::sys::time::SystemTime;
-use crate::sys::unsupported;
+use crate::sys::{cvt_real, unsupported};
 
 use crate::default::Default;
 use crate::ffi::{CStr, CString, OsStr, OsString};
 use crate::io::{self, Error, IoSlice, IoSliceMut, SeekFrom};
 use crate::io::{BorrowedBuf, BorrowedCursor};
 use crate::mem;
-use crate::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd};
+use crate::os::unix::io::{AsRawFd, FromRawFd};
 use crate::os::unix::prelude::*;
 use crate::ptr;
 use crate::sync::Arc;
 use crate::sys::fd::FileDesc;
-use crate::sys_common::{AsInner, AsInnerMut, FromInner, IntoInner};
+use crate::sys_common::{AsInner, FromInner, IntoInner};
+
+mod scratch;
+mod server;
+mod vfs;
+
+pub use scratch::set_temporary_files;
+pub use server::set_server_directories;
+pub use vfs::{getcwd, set_embedded, set_mounts};
+
+pub fn scratch_dir() -> PathBuf {
+    PathBuf::from(scratch::DIR)
+}
+
+/// Creates a temporary file with no name in the scratch directory, which is
+/// deleted once it is closed.
+pub fn unnamed_temp_file() -> io::Result<File> {
+    scratch::create_unnamed().map(File::Scratch)
+}
 
 impl AsInner<stat64> for FileAttr {
     fn as_inner(&self) -> &stat64 {
-        // Should be impossible, but things transmute the return value of this
-        // to a raw libc type.
-        unimplemented!();
+        &self.stat
     }
 }
 
@@ -35,32 +51,56 @@ impl FileTimes {
     pub fn set_created(&mut self, t: SystemTime) {}
 }
 
-pub struct ReadDir(!);
+pub enum ReadDir {
+    Server(server::ServerReadDir),
+    Vfs(vfs::VfsReadDir),
+}
 
-pub struct DirEntry(!);
+pub enum DirEntry {
+    Server(server::ServerDirEntry),
+    Vfs(vfs::VfsDirEntry),
+}
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct FilePermissions {
     mode: mode_t,
 }
-pub struct FileType(!);
 
-pub struct FileAttr(!);
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FileType {
+    mode: mode_t,
+}
+
+// Nothing here comes from `stat(2)`; the fields that matter are filled in by
+// whatever produced the file, and the rest stay zeroed.
+#[derive(Clone)]
+pub struct FileAttr {
+    stat: stat64,
+}
 
 #[derive(Debug)]
 pub struct DirBuilder {}
 
 impl FileAttr {
+    fn synthetic(mode: mode_t, size: u64) -> FileAttr {
+        // SAFETY: All zeros is a valid representation for `stat64`.
+        let mut stat: stat64 = unsafe { mem::zeroed() };
+        stat.st_mode = mode;
+        stat.st_nlink = 1;
+        stat.st_size = size as _;
+        FileAttr { stat }
+    }
+
     pub fn size(&self) -> u64 {
-        self.0
+        self.stat.st_size as u64
     }
 
     pub fn perm(&self) -> FilePermissions {
-        self.0
+        FilePermissions { mode: self.stat.st_mode as mode_t }
     }
 
     pub fn file_type(&self) -> FileType {
-        self.0
+        FileType { mode: self.stat.st_mode as mode_t }
     }
 
     pub fn modified(&self) -> io::Result<SystemTime> {
@@ -76,73 +116,52 @@ impl FileAttr {
     }
 }
 
-impl Clone for FileAttr {
-    fn clone(&self) -> FileAttr {
-        self.0
-    }
-}
-
 impl FilePermissions {
     pub fn readonly(&self) -> bool {
-        false
+        // check if any class (owner, group, others) has write permission
+        self.mode & 0o222 == 0
     }
 
-    pub fn set_readonly(&mut self, _readonly: bool) {}
+    pub fn set_readonly(&mut self, readonly: bool) {
+        if readonly {
+            self.mode &= !0o222;
+        } else {
+            self.mode |= 0o222;
+        }
+    }
 
     pub fn mode(&self) -> u32 {
-        unreachable!("can't create this")
+        self.mode as u32
     }
 }
 
 impl FileType {
     pub fn is_dir(&self) -> bool {
-        self.0
+        self.is(libc::S_IFDIR)
     }
 
     pub fn is_file(&self) -> bool {
-        self.0
+        self.is(libc::S_IFREG)
     }
 
     pub fn is_symlink(&self) -> bool {
-        self.0
+        self.is(libc::S_IFLNK)
     }
 
     pub fn is(&self, mode: mode_t) -> bool {
-        self.0
-    }
-}
-
-impl Clone for FileType {
-    fn clone(&self) -> FileType {
-        self.0
-    }
-}
-
-impl Copy for FileType {}
-
-impl PartialEq for FileType {
-    fn eq(&self, _other: &FileType) -> bool {
-        self.0
-    }
-}
-
-impl Eq for FileType {}
-
-impl Hash for FileType {
-    fn hash<H: Hasher>(&self, _h: &mut H) {
-        self.0
-    }
-}
-
-impl fmt::Debug for FileType {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0
+        self.mode & libc::S_IFMT == mode
     }
 }
 
 impl fmt::Debug for ReadDir {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // This will only be called from std::fs::ReadDir, which will add a "ReadDir()" frame.
+        // Thus the result will be e g 'ReadDir("/home")'
+        let root = match self {
+            ReadDir::Server(dir) => dir.root(),
+            ReadDir::Vfs(dir) => dir.root(),
+        };
+        fmt::Debug::fmt(root, f)
     }
 }
 
@@ -150,33 +169,53 @@ impl Iterator for ReadDir {
     type Item = io::Result<DirEntry>;
 
     fn next(&mut self) -> Option<io::Result<DirEntry>> {
-        self.0
+        match self {
+            ReadDir::Server(dir) => dir.next().map(|entry| entry.map(DirEntry::Server)),
+            ReadDir::Vfs(dir) => dir.next().map(|entry| Ok(DirEntry::Vfs(entry))),
+        }
     }
 }
 
 impl DirEntry {
     pub fn path(&self) -> PathBuf {
-        self.0
+        match self {
+            DirEntry::Server(entry) => entry.path(),
+            DirEntry::Vfs(entry) => entry.path(),
+        }
     }
 
     pub fn file_name(&self) -> OsString {
-        self.0
+        self.file_name_os_str().to_os_string()
     }
 
     pub fn metadata(&self) -> io::Result<FileAttr> {
-        unsupported()
+        match self {
+            DirEntry::Server(entry) => entry.metadata(),
+            DirEntry::Vfs(entry) => entry.metadata(),
+        }
     }
 
     pub fn file_type(&self) -> io::Result<FileType> {
-        unsupported()
+        match self {
+            DirEntry::Server(entry) => entry.file_type(),
+            DirEntry::Vfs(entry) => entry.file_type(),
+        }
     }
 
     pub fn ino(&self) -> u64 {
-        panic!("no sensible answer for ino")
+        match self {
+            DirEntry::Server(entry) => entry.ino(),
+            // Nothing in memory has an inode; every entry gets the same
+            // made-up number.
+            DirEntry::Vfs(_) => 0,
+        }
     }
 
     pub fn file_name_os_str(&self) -> &OsStr {
-        self.0
+        match self {
+            DirEntry::Server(entry) => entry.file_name_os_str(),
+            DirEntry::Vfs(entry) => entry.file_name_os_str(),
+        }
     }
 }
 
@@ -185,17 +224,31 @@ impl OpenOptions {
         OpenOptions::default()
     }
 
-    pub fn read(&mut self, _read: bool) {}
-    pub fn write(&mut self, _write: bool) {}
-    pub fn append(&mut self, _append: bool) {}
-    pub fn truncate(&mut self, _truncate: bool) {}
-    pub fn create(&mut self, _create: bool) {}
-    pub fn create_new(&mut self, _create_new: bool) {}
+    pub fn read(&mut self, read: bool) {
+        self.read = read;
+    }
+    pub fn write(&mut self, write: bool) {
+        self.write = write;
+    }
+    pub fn append(&mut self, append: bool) {
+        self.append = append;
+    }
+    pub fn truncate(&mut self, truncate: bool) {
+        self.truncate = truncate;
+    }
+    pub fn create(&mut self, create: bool) {
+        self.create = create;
+    }
+    pub fn create_new(&mut self, create_new: bool) {
+        self.create_new = create_new;
+    }
 
     pub fn custom_flags(&mut self, flags: i32) {
         self.custom_flags = flags;
     }
-    pub fn mode(&mut self, mode: u32) {}
+    pub fn mode(&mut self, mode: u32) {
+        self.mode = mode as mode_t;
+    }
 
     fn get_access_mode(&self) -> io::Result<c_int> {
         match (self.read, self.write, self.append) {
@@ -234,36 +287,67 @@ impl OpenOptions {
 }
 
 impl File {
-    pub fn open(_path: &Path, _opts: &OpenOptions) -> io::Result<File> {
-        unsupported()
+    pub fn open(path: &Path, opts: &OpenOptions) -> io::Result<File> {
+        let path = &vfs::absolute(path);
+        if let Some(found) = vfs::lookup(path) {
+            return vfs::VfsFile::open(&found, opts).map(File::Vfs);
+        }
+        match scratch::lookup(path) {
+            Some(scratch::Lookup::File(name)) => scratch::open(name, opts).map(File::Scratch),
+            Some(scratch::Lookup::Dir) => Err(io::const_io_error!(
+                io::ErrorKind::IsADirectory,
+                "the scratch directory cannot be opened as a file",
+            )),
+            None => server::ServerFile::open(path, opts).map(File::Server),
+        }
     }
 
     pub fn file_attr(&self) -> io::Result<FileAttr> {
-        unsupported()
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.file_attr(),
+            File::Server(file) => file.file_attr(),
+            File::Vfs(file) => file.file_attr(),
+        }
     }
 
     pub fn fsync(&self) -> io::Result<()> {
-        unsupported()
+        match self {
+            File::Fd(_) => unsupported(),
+            // Temporary files never need to reach the disk, and the others
+            // are never written.
+            File::Scratch(_) | File::Server(_) | File::Vfs(_) => Ok(()),
+        }
     }
 
     pub fn datasync(&self) -> io::Result<()> {
-        unsupported()
+        self.fsync()
     }
 
-    pub fn truncate(&self, _size: u64) -> io::Result<()> {
-        unsupported()
+    pub fn truncate(&self, size: u64) -> io::Result<()> {
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.set_len(size),
+            File::Server(_) => Err(server::read_only()),
+            File::Vfs(_) => Err(vfs::read_only()),
+        }
     }
 
-    pub fn read(&self, _buf: &mut [u8]) -> io::Result<usize> {
-        unsupported()
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.read(buf),
+            File::Server(file) => file.read(buf),
+            File::Vfs(file) => file.read(buf),
+        }
     }
 
-    pub fn read_vectored(&self, _bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
-        unsupported()
+    pub fn read_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        io::default_read_vectored(|buf| self.read(buf), bufs)
     }
 
     pub fn read_vectored_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> io::Result<usize> {
-        unsupported()
+        io::default_read_vectored(|buf| self.read_at(buf, offset), bufs)
     }
 
     pub fn is_read_vectored(&self) -> bool {
@@ -271,19 +355,24 @@ impl File {
     }
 
     pub fn read_buf(&self, cursor: BorrowedCursor<'_>) -> io::Result<()> {
-        self.0.read_buf(cursor)
+        io::default_read_buf(|buf| self.read(buf), cursor)
     }
 
-    pub fn write(&self, _buf: &[u8]) -> io::Result<usize> {
-        unsupported()
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.write(buf),
+            File::Server(_) => Err(server::read_only()),
+            File::Vfs(_) => Err(vfs::read_only()),
+        }
     }
 
-    pub fn write_vectored(&self, _bufs: &[IoSlice<'_>]) -> io::Result<usize> {
-        unsupported()
+    pub fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        io::default_write_vectored(|buf| self.write(buf), bufs)
     }
 
     pub fn write_vectored_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> io::Result<usize> {
-        unsupported()
+        io::default_write_vectored(|buf| self.write_at(buf, offset), bufs)
     }
 
     pub fn is_write_vectored(&self) -> bool {
@@ -291,15 +380,37 @@ impl File {
     }
 
     pub fn flush(&self) -> io::Result<()> {
-        unsupported()
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(_) | File::Server(_) | File::Vfs(_) => Ok(()),
+        }
     }
 
-    pub fn seek(&self, _pos: SeekFrom) -> io::Result<u64> {
-        unsupported()
+    pub fn seek(&self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.seek(pos),
+            File::Server(file) => file.seek(pos),
+            File::Vfs(file) => file.seek(pos),
+        }
+    }
+
+    /// The descriptor behind the file, if it has a real one.
+    pub fn into_fd(self) -> Option<FileDesc> {
+        match self {
+            File::Fd(fd) => Some(fd),
+            File::Server(file) => Some(file.into_fd()),
+            File::Scratch(_) | File::Vfs(_) => None,
+        }
     }
 
     pub fn duplicate(&self) -> io::Result<File> {
-        unsupported()
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.duplicate().map(File::Scratch),
+            File::Server(file) => file.duplicate().map(File::Server),
+            File::Vfs(file) => file.duplicate().map(File::Vfs),
+        }
     }
 
     pub fn set_permissions(&self, _perm: FilePermissions) -> io::Result<()> {
@@ -311,11 +422,21 @@ impl File {
     }
 
     pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
-        unsupported()
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.read_at(buf, offset),
+            File::Server(file) => file.read_at(buf, offset),
+            File::Vfs(file) => file.read_at(buf, offset),
+        }
     }
 
     pub fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
-        unsupported()
+        match self {
+            File::Fd(_) => unsupported(),
+            File::Scratch(file) => file.write_at(buf, offset),
+            File::Server(_) => Err(server::read_only()),
+            File::Vfs(_) => Err(vfs::read_only()),
+        }
     }
 }
 
@@ -329,26 +450,51 @@ impl DirBuilder {
     }
 
     pub fn set_mode(&mut self, _mode: u32) {
-        panic!("not supported")
+        // Nothing is created with it anyway, since `mkdir` fails.
     }
 }
 
 impl fmt::Debug for File {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            File::Fd(fd) => f.debug_struct("File").field("fd", &fd.as_raw_fd()).finish(),
+            File::Scratch(_) => f.debug_struct("File").field("kind", &"scratch").finish(),
+            File::Server(file) => {
+                f.debug_struct("File").field("fd", &file.fd().as_raw_fd()).finish()
+            }
+            File::Vfs(_) => f.debug_struct("File").field("kind", &"mounted").finish(),
+        }
     }
 }
 
-pub fn readdir(_p: &Path) -> io::Result<ReadDir> {
-    unsupported()
+pub fn readdir(p: &Path) -> io::Result<ReadDir> {
+    let p = &vfs::absolute(p);
+    match vfs::lookup(p) {
+        Some(found) => vfs::readdir(found, p).map(ReadDir::Vfs),
+        None => server::readdir(p).map(ReadDir::Server),
+    }
 }
 
-pub fn unlink(_p: &Path) -> io::Result<()> {
-    unsupported()
+pub fn unlink(p: &Path) -> io::Result<()> {
+    let p = &vfs::absolute(p);
+    match scratch::lookup(p) {
+        Some(scratch::Lookup::File(name)) => scratch::unlink(name),
+        Some(scratch::Lookup::Dir) => Err(io::const_io_error!(
+            io::ErrorKind::IsADirectory,
+            "the scratch directory cannot be removed",
+        )),
+        None => unsupported(),
+    }
 }
 
-pub fn rename(_old: &Path, _new: &Path) -> io::Result<()> {
-    unsupported()
+pub fn rename(old: &Path, new: &Path) -> io::Result<()> {
+    let (old, new) = (&vfs::absolute(old), &vfs::absolute(new));
+    match (scratch::lookup(old), scratch::lookup(new)) {
+        (Some(scratch::Lookup::File(old)), Some(scratch::Lookup::File(new))) => {
+            scratch::rename(old, new)
+        }
+        _ => unsupported(),
+    }
 }
 
 pub fn set_perm(_p: &Path, perm: FilePermissions) -> io::Result<()> {
@@ -363,8 +509,19 @@ pub fn remove_dir_all(_path: &Path) -> io::Result<()> {
     unsupported()
 }
 
-pub fn try_exists(_path: &Path) -> io::Result<bool> {
-    unsupported()
+pub fn try_exists(path: &Path) -> io::Result<bool> {
+    match stat(path) {
+        Ok(_) => Ok(true),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+pub fn chdir(p: &Path) -> io::Result<()> {
+    match vfs::lookup(&vfs::absolute(p)) {
+        Some(found) => vfs::chdir(&found),
+        None => unsupported(),
+    }
 }
 
 pub fn readlink(_p: &Path) -> io::Result<PathBuf> {
@@ -379,16 +536,35 @@ pub fn link(_src: &Path, _dst: &Path) -> io::Result<()> {
     unsupported()
 }
 
-pub fn stat(_p: &Path) -> io::Result<FileAttr> {
-    unsupported()
+pub fn stat(p: &Path) -> io::Result<FileAttr> {
+    let p = &vfs::absolute(p);
+    if let Some(found) = vfs::lookup(p) {
+        return vfs::stat(&found);
+    }
+    match scratch::lookup(p) {
+        Some(entry) => scratch::stat(&entry),
+        None => server::stat(p),
+    }
 }
 
-pub fn lstat(_p: &Path) -> io::Result<FileAttr> {
-    unsupported()
+pub fn lstat(p: &Path) -> io::Result<FileAttr> {
+    let p = &vfs::absolute(p);
+    if let Some(found) = vfs::lookup(p) {
+        return vfs::stat(&found);
+    }
+    match scratch::lookup(p) {
+        // There are no symlinks in mounts or the scratch directory.
+        Some(entry) => scratch::stat(&entry),
+        None => server::lstat(p),
+    }
 }
 
-pub fn canonicalize(_p: &Path) -> io::Result<PathBuf> {
-    unsupported()
+pub fn canonicalize(p: &Path) -> io::Result<PathBuf> {
+    let p = &vfs::absolute(p);
+    match vfs::lookup(p) {
+        Some(found) => vfs::canonicalize(found),
+        None => server::canonicalize(p),
+    }
 }
 
 pub fn copy(_from: &Path, _to: &Path) -> io::Result<u64> {
@@ -462,7 +638,13 @@ use libc::stat as stat64;
 #[cfg(target_os = "linux")]
 use libc::stat64;
 
-pub struct File(FileDesc);
+pub enum File {
+    // Only reachable by converting from an `OwnedFd`; no I/O goes through it.
+    Fd(FileDesc),
+    Scratch(scratch::ScratchFile),
+    Server(server::ServerFile),
+    Vfs(vfs::VfsFile),
+}
 
 struct Dir(*mut libc::DIR);
 
@@ -536,7 +718,7 @@ pub struct OpenOptions {
 
 impl FromInner<u32> for FilePermissions {
     fn from_inner(mode: u32) -> FilePermissions {
-        panic!("oh no! file permissions don't exist here!")
+        FilePermissions { mode: mode as mode_t }
     }
 }
 
@@ -551,12 +733,6 @@ fn cstr(path: &Path) -> io::Result<CString> {
     Ok(CString::new(path.as_os_str().as_bytes())?)
 }
 
-impl AsInner<FileDesc> for File {
-    fn as_inner(&self) -> &FileDesc {
-        &self.0
-    }
-}
-
 impl IntoInner<!> for ! {
     fn into_inner(self) -> ! {
         match self {}
@@ -569,45 +745,15 @@ impl AsInner<!> for ! {
     }
 }
 
-impl AsInnerMut<FileDesc> for File {
-    fn as_inner_mut(&mut self) -> &mut FileDesc {
-        &mut self.0
-    }
-}
-
-impl IntoInner<FileDesc> for File {
-    fn into_inner(self) -> FileDesc {
-        self.0
-    }
-}
-
 impl FromInner<FileDesc> for File {
     fn from_inner(file_desc: FileDesc) -> Self {
-        Self(file_desc)
-    }
-}
-
-impl AsFd for File {
-    fn as_fd(&self) -> BorrowedFd<'_> {
-        self.0.as_fd()
-    }
-}
-
-impl AsRawFd for File {
-    fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
-    }
-}
-
-impl IntoRawFd for File {
-    fn into_raw_fd(self) -> RawFd {
-        self.0.into_raw_fd()
+        File::Fd(file_desc)
     }
 }
 
 impl FromRawFd for File {
     unsafe fn from_raw_fd(raw_fd: RawFd) -> Self {
-        Self(FromRawFd::from_raw_fd(raw_fd))
+        File::Fd(FromRawFd::from_raw_fd(raw_fd))
     }
 }
 
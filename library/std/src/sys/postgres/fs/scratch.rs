@@ -0,0 +1,293 @@
+//! The scratch directory returned by `env::temp_dir()`.
+//!
+//! Every file in it is a Postgres temporary file, created through the host's
+//! `TemporaryFiles`. The directory itself only exists in here: it is flat, it
+//! maps names to temporary files of the current transaction, and it starts out
+//! empty again once that transaction is over.
+
+use super::{FileAttr, OpenOptions};
+use crate::collections::BTreeMap;
+use crate::ffi::{OsStr, OsString};
+use crate::io::{self, SeekFrom};
+use crate::mem;
+use crate::os::postgres::fs::{TemporaryFiles, Vfd};
+use crate::path::{Component, Path};
+use crate::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use crate::sync::{Arc, Mutex, PoisonError, RwLock};
+use crate::sys::unsupported;
+
+pub const DIR: &str = "/pgsql_tmp";
+const DIR_NAME: &str = "pgsql_tmp";
+
+static TEMPORARY_FILES: RwLock<Option<&'static dyn TemporaryFiles>> = RwLock::new(None);
+
+static ENTRIES: Mutex<Entries> = Mutex::new(Entries { xact: 0, names: BTreeMap::new() });
+
+struct Entries {
+    xact: u64,
+    names: BTreeMap<OsString, Arc<Node>>,
+}
+
+pub fn set_temporary_files(files: &'static dyn TemporaryFiles) {
+    *TEMPORARY_FILES.write().unwrap_or_else(PoisonError::into_inner) = Some(files);
+}
+
+fn backend() -> io::Result<&'static dyn TemporaryFiles> {
+    match *TEMPORARY_FILES.read().unwrap_or_else(PoisonError::into_inner) {
+        Some(files) => Ok(files),
+        None => unsupported(),
+    }
+}
+
+/// What a path names, if it is inside the scratch directory.
+pub enum Lookup<'a> {
+    Dir,
+    File(&'a OsStr),
+}
+
+pub fn lookup(path: &Path) -> Option<Lookup<'_>> {
+    // `components` already folds away `.` and repeated separators, but keeps
+    // `..`, so anything trying to climb out of the directory doesn't match.
+    let mut components = path.components();
+    if components.next() != Some(Component::RootDir) {
+        return None;
+    }
+    match components.next() {
+        Some(Component::Normal(dir)) if dir == DIR_NAME => {}
+        _ => return None,
+    }
+    match (components.next(), components.next()) {
+        (None, _) => Some(Lookup::Dir),
+        (Some(Component::Normal(name)), None) => Some(Lookup::File(name)),
+        _ => None,
+    }
+}
+
+/// Runs `f` on the names in the directory, after forgetting the ones left
+/// over from an earlier transaction. Whatever `f` returns is dropped after the
+/// lock is released, so that closing files never happens while holding it.
+fn with_entries<R>(
+    backend: &'static dyn TemporaryFiles,
+    f: impl FnOnce(&mut BTreeMap<OsString, Arc<Node>>) -> R,
+) -> R {
+    let mut entries = ENTRIES.lock().unwrap_or_else(PoisonError::into_inner);
+    let xact = backend.transaction();
+    let stale = if entries.xact != xact {
+        entries.xact = xact;
+        mem::take(&mut entries.names)
+    } else {
+        BTreeMap::new()
+    };
+    let ret = f(&mut entries.names);
+    drop(entries);
+    drop(stale);
+    ret
+}
+
+fn not_found() -> io::Error {
+    io::const_io_error!(io::ErrorKind::NotFound, "no such file in the scratch directory")
+}
+
+// A temporary file, shared by its name in the directory and every handle open
+// on it. It is closed, and with that deleted, once all of them are gone.
+struct Node {
+    backend: &'static dyn TemporaryFiles,
+    vfd: Vfd,
+    xact: u64,
+}
+
+impl Node {
+    fn vfd(&self) -> io::Result<Vfd> {
+        if self.backend.transaction() == self.xact {
+            Ok(self.vfd)
+        } else {
+            Err(io::const_io_error!(
+                io::ErrorKind::NotFound,
+                "temporary file was removed at the end of its transaction",
+            ))
+        }
+    }
+
+    fn attr(&self) -> io::Result<FileAttr> {
+        let size = self.backend.size(self.vfd()?)?;
+        Ok(FileAttr::synthetic(libc::S_IFREG | 0o600, size))
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        // Postgres already closed the files of earlier transactions, and their
+        // vfd may well have been reused since.
+        if self.backend.transaction() == self.xact {
+            self.backend.close(self.vfd);
+        }
+    }
+}
+
+pub struct ScratchFile {
+    // All I/O goes through the vfd. It has no descriptor of its own to hand
+    // out: the one fd.c has behind it would let writes escape
+    // `temp_file_limit`, and could be closed and reused by fd.c at any time.
+    node: Arc<Node>,
+    pos: Arc<AtomicU64>,
+    read: bool,
+    write: bool,
+    append: bool,
+}
+
+pub fn open(name: &OsStr, opts: &OpenOptions) -> io::Result<ScratchFile> {
+    let backend = backend()?;
+    opts.get_access_mode()?;
+    opts.get_creation_mode()?;
+
+    let node = with_entries(backend, |names| match names.get(name) {
+        Some(_) if opts.create_new => Err(io::const_io_error!(
+            io::ErrorKind::AlreadyExists,
+            "file already exists in the scratch directory",
+        )),
+        Some(node) => Ok(node.clone()),
+        None if opts.create || opts.create_new => {
+            let vfd = backend.create()?;
+            let node = Arc::new(Node { backend, vfd, xact: backend.transaction() });
+            names.insert(name.to_owned(), node.clone());
+            Ok(node)
+        }
+        None => Err(not_found()),
+    })?;
+
+    let handle = ScratchFile::new(node, opts.read, opts.write || opts.append, opts.append)?;
+    if opts.truncate {
+        backend.set_len(handle.node.vfd()?, 0)?;
+    }
+    Ok(handle)
+}
+
+/// Creates a temporary file that is open for reading and writing, but has no
+/// name in the directory, like `tmpfile(3)`. It is deleted once closed.
+pub fn create_unnamed() -> io::Result<ScratchFile> {
+    let backend = backend()?;
+    let vfd = backend.create()?;
+    let node = Arc::new(Node { backend, vfd, xact: backend.transaction() });
+    ScratchFile::new(node, true, true, false)
+}
+
+pub fn stat(path: &Lookup<'_>) -> io::Result<FileAttr> {
+    match *path {
+        Lookup::Dir => Ok(FileAttr::synthetic(libc::S_IFDIR | 0o700, 0)),
+        Lookup::File(name) => {
+            let backend = backend()?;
+            let node = with_entries(backend, |names| names.get(name).cloned());
+            node.ok_or_else(not_found)?.attr()
+        }
+    }
+}
+
+pub fn unlink(name: &OsStr) -> io::Result<()> {
+    let backend = backend()?;
+    // Open handles keep the file alive, same as unlinking an open file.
+    let removed = with_entries(backend, |names| names.remove(name));
+    removed.map(drop).ok_or_else(not_found)
+}
+
+pub fn rename(from: &OsStr, to: &OsStr) -> io::Result<()> {
+    let backend = backend()?;
+    let replaced = with_entries(backend, |names| -> io::Result<_> {
+        let node = names.remove(from).ok_or_else(not_found)?;
+        Ok(names.insert(to.to_owned(), node))
+    })?;
+    drop(replaced);
+    Ok(())
+}
+
+impl ScratchFile {
+    fn new(node: Arc<Node>, read: bool, write: bool, append: bool) -> io::Result<ScratchFile> {
+        node.vfd()?;
+        Ok(ScratchFile { node, pos: Arc::new(AtomicU64::new(0)), read, write, append })
+    }
+
+    fn readable(&self) -> io::Result<Vfd> {
+        if !self.read {
+            return Err(io::const_io_error!(
+                io::ErrorKind::PermissionDenied,
+                "file was not opened for reading",
+            ));
+        }
+        self.node.vfd()
+    }
+
+    fn writable(&self) -> io::Result<Vfd> {
+        if !self.write {
+            return Err(io::const_io_error!(
+                io::ErrorKind::PermissionDenied,
+                "file was not opened for writing",
+            ));
+        }
+        self.node.vfd()
+    }
+
+    pub fn file_attr(&self) -> io::Result<FileAttr> {
+        self.node.attr()
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos.load(Relaxed);
+        let n = self.read_at(buf, pos)?;
+        self.pos.store(pos + n as u64, Relaxed);
+        Ok(n)
+    }
+
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let vfd = self.readable()?;
+        self.node.backend.read_at(vfd, buf, offset)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let pos = if self.append {
+            self.node.backend.size(self.writable()?)?
+        } else {
+            self.pos.load(Relaxed)
+        };
+        let n = self.write_at(buf, pos)?;
+        self.pos.store(pos + n as u64, Relaxed);
+        Ok(n)
+    }
+
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        let vfd = self.writable()?;
+        self.node.backend.write_at(vfd, buf, offset)
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => {
+                let size = self.node.backend.size(self.node.vfd()?)?;
+                size.checked_add_signed(n)
+            }
+            SeekFrom::Current(n) => self.pos.load(Relaxed).checked_add_signed(n),
+        };
+        let new = new.filter(|&n| i64::try_from(n).is_ok()).ok_or_else(|| {
+            io::const_io_error!(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.pos.store(new, Relaxed);
+        Ok(new)
+    }
+
+    pub fn set_len(&self, size: u64) -> io::Result<()> {
+        let vfd = self.writable()?;
+        self.node.backend.set_len(vfd, size)
+    }
+
+    pub fn duplicate(&self) -> io::Result<ScratchFile> {
+        Ok(ScratchFile {
+            node: self.node.clone(),
+            pos: self.pos.clone(),
+            read: self.read,
+            write: self.write,
+            append: self.append,
+        })
+    }
+}
@@ -0,0 +1,368 @@
+//! Read-only access to the server directories the host allowlisted.
+//!
+//! A path is checked twice. As given, it must be absolute, free of `..` and
+//! below one of the roots. Then it is resolved with `realpath(3)`, and must
+//! still be below the resolved root, so a symlink can't lead anywhere else.
+//! Files are then opened from the root one component at a time with
+//! `O_NOFOLLOW`, so a symlink swapped in after the check is not followed
+//! either.
+
+use super::{cstr, stat64, Dir, FileAttr, FileType, OpenOptions};
+use crate::cmp;
+use crate::ffi::{CStr, OsStr, OsString};
+use crate::io::{self, SeekFrom};
+use crate::mem;
+use crate::os::postgres::fs::ServerFileAccess;
+use crate::os::unix::prelude::*;
+use crate::path::{Component, Path, PathBuf};
+use crate::ptr;
+use crate::sync::{Arc, PoisonError, RwLock};
+use crate::sys::fd::FileDesc;
+use crate::sys::{cvt_real, cvt_real_r, real_last_os_error_use_carefully, unsupported};
+
+use libc::c_int;
+#[cfg(target_os = "macos")]
+use libc::{fstat as fstat64, fstatat as fstatat64, lseek as lseek64, off_t as off64_t};
+#[cfg(target_os = "linux")]
+use libc::{fstat64, fstatat64, lseek64, off64_t};
+
+static SERVER_DIRECTORIES: RwLock<Option<ServerDirectories>> = RwLock::new(None);
+
+struct ServerDirectories {
+    roots: Vec<PathBuf>,
+    access: &'static dyn ServerFileAccess,
+}
+
+pub fn set_server_directories(roots: Vec<PathBuf>, access: &'static dyn ServerFileAccess) {
+    *SERVER_DIRECTORIES.write().unwrap_or_else(PoisonError::into_inner) =
+        Some(ServerDirectories { roots, access });
+}
+
+fn outside() -> io::Error {
+    io::const_io_error!(
+        io::ErrorKind::PermissionDenied,
+        "path is outside of the readable server directories",
+    )
+}
+
+pub fn read_only() -> io::Error {
+    io::const_io_error!(io::ErrorKind::PermissionDenied, "server files can only be read")
+}
+
+/// Returns the root `path` is below, as given by the host, once the current
+/// user is allowed to read server files at all.
+fn root_of(path: &Path) -> io::Result<PathBuf> {
+    let (access, root) = {
+        let dirs = SERVER_DIRECTORIES.read().unwrap_or_else(PoisonError::into_inner);
+        let Some(dirs) = &*dirs else { return unsupported() };
+        // `starts_with` compares whole components, so `/srv/data2` is not below
+        // `/srv/data`, and a relative path is below no root at all.
+        let root = dirs.roots.iter().find(|root| root.is_absolute() && path.starts_with(root));
+        (dirs.access, root.cloned())
+    };
+    if !access.may_read_server_files() {
+        return Err(io::const_io_error!(
+            io::ErrorKind::PermissionDenied,
+            "must have privileges of pg_read_server_files to read server files",
+        ));
+    }
+    if path.components().any(|component| component == Component::ParentDir) {
+        return Err(outside());
+    }
+    root.ok_or_else(outside)
+}
+
+/// A path below one of the roots, with every symlink resolved.
+struct Resolved {
+    root: PathBuf,
+    path: PathBuf,
+}
+
+fn resolve(path: &Path) -> io::Result<Resolved> {
+    let root = realpath(&root_of(path)?)?;
+    let path = realpath(path)?;
+    if !path.starts_with(&root) {
+        return Err(outside());
+    }
+    Ok(Resolved { root, path })
+}
+
+fn realpath(path: &Path) -> io::Result<PathBuf> {
+    let path = cstr(path)?;
+    let r = unsafe { libc::realpath(path.as_ptr(), ptr::null_mut()) };
+    if r.is_null() {
+        return Err(real_last_os_error_use_carefully());
+    }
+    Ok(PathBuf::from(OsString::from_vec(unsafe {
+        let buf = CStr::from_ptr(r).to_bytes().to_vec();
+        libc::free(r as *mut _);
+        buf
+    })))
+}
+
+fn open_at(dir: c_int, name: &OsStr, flags: c_int) -> io::Result<FileDesc> {
+    let name = cstr(Path::new(name))?;
+    let fd = cvt_real_r(|| unsafe {
+        libc::openat(
+            dir,
+            name.as_ptr(),
+            libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOFOLLOW | flags,
+        )
+    })?;
+    Ok(unsafe { FileDesc::from_raw_fd(fd) })
+}
+
+/// Opens the directory `resolved` is in, walking down from its root. Returns
+/// it with the last component, or the root itself and `None` if `resolved` is
+/// the root.
+fn walk(resolved: &Resolved) -> io::Result<(FileDesc, Option<&OsStr>)> {
+    let mut dir = open_at(libc::AT_FDCWD, resolved.root.as_os_str(), libc::O_DIRECTORY)?;
+    let mut components =
+        resolved.path.strip_prefix(&resolved.root).map_err(|_| outside())?.components();
+    let last = components.next_back();
+    for component in components {
+        dir = open_at(dir.as_raw_fd(), component.as_os_str(), libc::O_DIRECTORY)?;
+    }
+    Ok((dir, last.map(|component| component.as_os_str())))
+}
+
+fn open(resolved: &Resolved, flags: c_int) -> io::Result<FileDesc> {
+    let (dir, name) = walk(resolved)?;
+    let Some(name) = name else { return Ok(dir) };
+    // Opening a FIFO for reading blocks until there is a writer, which would
+    // hang the backend where no interrupt can reach it.
+    let fd = open_at(dir.as_raw_fd(), name, flags | libc::O_NONBLOCK)?;
+    unsafe {
+        let flags = cvt_real(libc::fcntl(fd.as_raw_fd(), libc::F_GETFL))?;
+        cvt_real(libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK))?;
+    }
+    Ok(fd)
+}
+
+fn fstat(fd: &FileDesc) -> io::Result<FileAttr> {
+    let mut stat: stat64 = unsafe { mem::zeroed() };
+    cvt_real(unsafe { fstat64(fd.as_raw_fd(), &mut stat) })?;
+    Ok(FileAttr { stat })
+}
+
+fn fstatat(dir: c_int, name: &OsStr) -> io::Result<FileAttr> {
+    let name = cstr(Path::new(name))?;
+    let mut stat: stat64 = unsafe { mem::zeroed() };
+    cvt_real(unsafe { fstatat64(dir, name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) })?;
+    Ok(FileAttr { stat })
+}
+
+pub fn stat(path: &Path) -> io::Result<FileAttr> {
+    let resolved = resolve(path)?;
+    match walk(&resolved)? {
+        (dir, None) => fstat(&dir),
+        (dir, Some(name)) => fstatat(dir.as_raw_fd(), name),
+    }
+}
+
+pub fn lstat(path: &Path) -> io::Result<FileAttr> {
+    // Everything but the last component is resolved as usual; the last one
+    // is looked at as it is, which is all a symlink below a root can show.
+    let root = root_of(path)?;
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if path != root.as_path() => {
+            let dir = open(&resolve(parent)?, libc::O_DIRECTORY)?;
+            fstatat(dir.as_raw_fd(), name)
+        }
+        _ => stat(path),
+    }
+}
+
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    resolve(path).map(|resolved| resolved.path)
+}
+
+pub fn readdir(path: &Path) -> io::Result<ServerReadDir> {
+    let fd = open(&resolve(path)?, libc::O_DIRECTORY)?;
+    let dirp = unsafe { libc::fdopendir(fd.as_raw_fd()) };
+    if dirp.is_null() {
+        return Err(real_last_os_error_use_carefully());
+    }
+    // The directory stream owns the descriptor now.
+    let _ = fd.into_raw_fd();
+    // Entries are named after the path as given, so that they pass the same
+    // checks when used again.
+    let inner = Arc::new(InnerReadDir { dirp: Dir(dirp), root: path.to_path_buf() });
+    Ok(ServerReadDir { inner, end_of_stream: false })
+}
+
+pub struct ServerReadDir {
+    inner: Arc<InnerReadDir>,
+    end_of_stream: bool,
+}
+
+struct InnerReadDir {
+    dirp: Dir,
+    root: PathBuf,
+}
+
+pub struct ServerDirEntry {
+    dir: Arc<InnerReadDir>,
+    name: OsString,
+    d_ino: u64,
+    d_type: u8,
+}
+
+impl ServerReadDir {
+    pub fn root(&self) -> &Path {
+        &self.inner.root
+    }
+}
+
+impl Iterator for ServerReadDir {
+    type Item = io::Result<ServerDirEntry>;
+
+    fn next(&mut self) -> Option<io::Result<ServerDirEntry>> {
+        if self.end_of_stream {
+            return None;
+        }
+        // The stream may be kept after the role or the allowlist changes.
+        if let Err(e) = root_of(&self.inner.root) {
+            self.end_of_stream = true;
+            return Some(Err(e));
+        }
+
+        unsafe {
+            loop {
+                crate::sys::os::clear_errno_use_carefully();
+                let entry_ptr = libc::readdir(self.inner.dirp.0);
+                if entry_ptr.is_null() {
+                    // Either an error or the end of the directory; in both
+                    // cases the next call to next() returns None. errno was
+                    // cleared beforehand to tell them apart.
+                    self.end_of_stream = true;
+                    return match crate::sys::os::real_errno_use_carefully() {
+                        0 => None,
+                        e => Some(Err(io::Error::from_raw_os_error(e))),
+                    };
+                }
+
+                // `d_name` may be shorter or longer than its declared size, so
+                // only ever go through raw pointers here.
+                let name = CStr::from_ptr(ptr::addr_of!((*entry_ptr).d_name).cast());
+                let name_bytes = name.to_bytes();
+                if name_bytes == b"." || name_bytes == b".." {
+                    continue;
+                }
+
+                return Some(Ok(ServerDirEntry {
+                    dir: Arc::clone(&self.inner),
+                    name: OsStr::from_bytes(name_bytes).to_os_string(),
+                    d_ino: ptr::addr_of!((*entry_ptr).d_ino).read() as u64,
+                    d_type: ptr::addr_of!((*entry_ptr).d_type).read(),
+                }));
+            }
+        }
+    }
+}
+
+impl ServerDirEntry {
+    pub fn path(&self) -> PathBuf {
+        self.dir.root.join(&self.name)
+    }
+
+    pub fn file_name(&self) -> OsString {
+        self.name.clone()
+    }
+
+    pub fn metadata(&self) -> io::Result<FileAttr> {
+        // Checked again like `ServerReadDir::next`, since entries outlive it.
+        root_of(&self.dir.root)?;
+        let dirfd = unsafe { libc::dirfd(self.dir.dirp.0) };
+        fstatat(dirfd, &self.name)
+    }
+
+    pub fn file_type(&self) -> io::Result<FileType> {
+        let mode = match self.d_type {
+            libc::DT_CHR => libc::S_IFCHR,
+            libc::DT_FIFO => libc::S_IFIFO,
+            libc::DT_LNK => libc::S_IFLNK,
+            libc::DT_REG => libc::S_IFREG,
+            libc::DT_SOCK => libc::S_IFSOCK,
+            libc::DT_DIR => libc::S_IFDIR,
+            libc::DT_BLK => libc::S_IFBLK,
+            _ => return self.metadata().map(|m| m.file_type()),
+        };
+        Ok(FileType { mode })
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.d_ino
+    }
+
+    pub fn file_name_os_str(&self) -> &OsStr {
+        &self.name
+    }
+}
+
+pub struct ServerFile(FileDesc);
+
+impl ServerFile {
+    pub fn open(path: &Path, opts: &OpenOptions) -> io::Result<ServerFile> {
+        let access = opts.get_access_mode()?;
+        opts.get_creation_mode()?;
+        if access != libc::O_RDONLY {
+            return Err(read_only());
+        }
+        open(&resolve(path)?, 0).map(ServerFile)
+    }
+
+    pub fn file_attr(&self) -> io::Result<FileAttr> {
+        fstat(&self.0)
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), READ_LIMIT);
+        let n = cvt_real(unsafe { libc::read(self.0.as_raw_fd(), buf.as_mut_ptr().cast(), len) })?;
+        Ok(n as usize)
+    }
+
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(target_os = "macos")]
+        use libc::pread as pread64;
+        #[cfg(target_os = "linux")]
+        use libc::pread64;
+
+        let len = cmp::min(buf.len(), READ_LIMIT);
+        let n = cvt_real(unsafe {
+            pread64(self.0.as_raw_fd(), buf.as_mut_ptr().cast(), len, offset as off64_t)
+        })?;
+        Ok(n as usize)
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> io::Result<u64> {
+        let (whence, pos) = match pos {
+            // Casting to `i64` is fine, too large values will end up as
+            // negative which will cause an error in `lseek64`.
+            SeekFrom::Start(off) => (libc::SEEK_SET, off as i64),
+            SeekFrom::End(off) => (libc::SEEK_END, off),
+            SeekFrom::Current(off) => (libc::SEEK_CUR, off),
+        };
+        let n = cvt_real(unsafe { lseek64(self.0.as_raw_fd(), pos as off64_t, whence) })?;
+        Ok(n as u64)
+    }
+
+    pub fn duplicate(&self) -> io::Result<ServerFile> {
+        self.0.duplicate().map(ServerFile)
+    }
+
+    pub fn fd(&self) -> &FileDesc {
+        &self.0
+    }
+
+    pub fn into_fd(self) -> FileDesc {
+        self.0
+    }
+}
+
+// The maximum read length for a single call, as on unix: at most `isize::MAX`,
+// and below `c_int::MAX` on macOS, which rejects anything larger.
+#[cfg(target_os = "macos")]
+const READ_LIMIT: usize = libc::c_int::MAX as usize - 1;
+#[cfg(not(target_os = "macos"))]
+const READ_LIMIT: usize = libc::ssize_t::MAX as usize;
@@ -0,0 +1,265 @@
+//! Read-only trees mounted by the host, and the embedded directory.
+//!
+//! Everything here lives in memory and is looked up through the `Vfs` trait.
+//! The current directory is virtual too: it can only be set to a mounted
+//! directory, and relative paths are resolved against it.
+
+use super::{FileAttr, FileType, OpenOptions};
+use crate::ffi::{OsStr, OsString};
+use crate::io::{self, SeekFrom};
+use crate::os::postgres::fs::{Embedded, Vfs, EMBEDDED_DIR};
+use crate::os::unix::prelude::*;
+use crate::path::{Component, Path, PathBuf};
+use crate::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use crate::sync::{Arc, PoisonError, RwLock};
+use crate::sys::unsupported;
+use crate::vec;
+
+static MOUNTS: RwLock<Vec<(PathBuf, &'static dyn Vfs)>> = RwLock::new(Vec::new());
+
+static EMBEDDED: RwLock<Option<&'static Embedded>> = RwLock::new(None);
+
+static CURRENT_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+pub fn set_mounts(mounts: Vec<(PathBuf, &'static dyn Vfs)>) {
+    *MOUNTS.write().unwrap_or_else(PoisonError::into_inner) = mounts;
+    *CURRENT_DIR.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+pub fn set_embedded(files: Option<&'static Embedded>) {
+    *EMBEDDED.write().unwrap_or_else(PoisonError::into_inner) = files;
+    *CURRENT_DIR.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Joins a relative `path` onto the current directory, if there is one.
+pub fn absolute(path: &Path) -> PathBuf {
+    match &*CURRENT_DIR.read().unwrap_or_else(PoisonError::into_inner) {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+pub fn getcwd() -> io::Result<PathBuf> {
+    match &*CURRENT_DIR.read().unwrap_or_else(PoisonError::into_inner) {
+        Some(dir) => Ok(dir.clone()),
+        None => unsupported(),
+    }
+}
+
+pub fn chdir(found: &Found) -> io::Result<()> {
+    match found.node()? {
+        Node::Dir => {
+            *CURRENT_DIR.write().unwrap_or_else(PoisonError::into_inner) = Some(found.path.clone());
+            Ok(())
+        }
+        Node::File(_) => Err(io::const_io_error!(io::ErrorKind::NotADirectory, "not a directory")),
+    }
+}
+
+/// A path below a mount point.
+pub struct Found {
+    vfs: &'static dyn Vfs,
+    /// The whole path, normalized.
+    path: PathBuf,
+    /// The path relative to the mount point, as the `Vfs` sees it.
+    rel: PathBuf,
+}
+
+enum Node {
+    File(&'static [u8]),
+    Dir,
+}
+
+/// Finds the mount `path` is below. `path` must be absolute.
+pub fn lookup(path: &Path) -> Option<Found> {
+    // There are no symlinks in here, so `..` can be resolved right away.
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            component => normalized.push(component),
+        }
+    }
+    if !normalized.has_root() {
+        return None;
+    }
+
+    let embedded = EMBEDDED
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .map(|files| (Path::new(EMBEDDED_DIR), files as &'static dyn Vfs));
+    let mounts = MOUNTS.read().unwrap_or_else(PoisonError::into_inner);
+    // The host's mounts come last, so they win a tie with the embedded one.
+    let (at, vfs) = embedded
+        .into_iter()
+        .chain(mounts.iter().map(|(at, vfs)| (at.as_path(), *vfs)))
+        .filter(|(at, _)| normalized.starts_with(at))
+        .max_by_key(|(at, _)| at.components().count())?;
+    let rel = normalized.strip_prefix(at).ok()?.to_path_buf();
+    Some(Found { vfs, path: normalized, rel })
+}
+
+impl Found {
+    fn node(&self) -> io::Result<Node> {
+        node(self.vfs, &self.rel)
+    }
+}
+
+fn node(vfs: &'static dyn Vfs, rel: &Path) -> io::Result<Node> {
+    if let Some(contents) = vfs.read(rel) {
+        Ok(Node::File(contents))
+    } else if vfs.list(rel).is_some() {
+        Ok(Node::Dir)
+    } else {
+        Err(io::const_io_error!(io::ErrorKind::NotFound, "no such file or directory"))
+    }
+}
+
+fn attr(node: Node) -> FileAttr {
+    match node {
+        Node::File(contents) => FileAttr::synthetic(libc::S_IFREG | 0o444, contents.len() as u64),
+        Node::Dir => FileAttr::synthetic(libc::S_IFDIR | 0o555, 0),
+    }
+}
+
+pub fn read_only() -> io::Error {
+    io::const_io_error!(io::ErrorKind::PermissionDenied, "mounted files can only be read")
+}
+
+pub fn stat(found: &Found) -> io::Result<FileAttr> {
+    found.node().map(attr)
+}
+
+pub fn canonicalize(found: Found) -> io::Result<PathBuf> {
+    found.node()?;
+    Ok(found.path)
+}
+
+pub fn readdir(found: Found, path: &Path) -> io::Result<VfsReadDir> {
+    let names = found.vfs.list(&found.rel).ok_or_else(|| match found.vfs.read(&found.rel) {
+        Some(_) => io::const_io_error!(io::ErrorKind::NotADirectory, "not a directory"),
+        None => io::const_io_error!(io::ErrorKind::NotFound, "no such file or directory"),
+    })?;
+    let dir = Arc::new(VfsDir { vfs: found.vfs, rel: found.rel, root: path.to_path_buf() });
+    Ok(VfsReadDir { dir, names: names.into_iter() })
+}
+
+struct VfsDir {
+    vfs: &'static dyn Vfs,
+    rel: PathBuf,
+    root: PathBuf,
+}
+
+pub struct VfsReadDir {
+    dir: Arc<VfsDir>,
+    names: vec::IntoIter<OsString>,
+}
+
+impl VfsReadDir {
+    pub fn root(&self) -> &Path {
+        &self.dir.root
+    }
+}
+
+impl Iterator for VfsReadDir {
+    type Item = VfsDirEntry;
+
+    fn next(&mut self) -> Option<VfsDirEntry> {
+        let name = self.names.next()?;
+        Some(VfsDirEntry { dir: Arc::clone(&self.dir), name })
+    }
+}
+
+pub struct VfsDirEntry {
+    dir: Arc<VfsDir>,
+    name: OsString,
+}
+
+impl VfsDirEntry {
+    pub fn path(&self) -> PathBuf {
+        self.dir.root.join(&self.name)
+    }
+
+    pub fn file_name(&self) -> OsString {
+        self.name.clone()
+    }
+
+    pub fn metadata(&self) -> io::Result<FileAttr> {
+        node(self.dir.vfs, &self.dir.rel.join(&self.name)).map(attr)
+    }
+
+    pub fn file_type(&self) -> io::Result<FileType> {
+        self.metadata().map(|attr| attr.file_type())
+    }
+
+    pub fn file_name_os_str(&self) -> &OsStr {
+        &self.name
+    }
+}
+
+pub struct VfsFile {
+    contents: &'static [u8],
+    pos: Arc<AtomicU64>,
+}
+
+impl VfsFile {
+    pub fn open(found: &Found, opts: &OpenOptions) -> io::Result<VfsFile> {
+        let access = opts.get_access_mode()?;
+        opts.get_creation_mode()?;
+        if access != libc::O_RDONLY {
+            return Err(read_only());
+        }
+        let contents = match found.node()? {
+            Node::File(contents) => contents,
+            Node::Dir => {
+                return Err(io::const_io_error!(
+                    io::ErrorKind::IsADirectory,
+                    "mounted directories cannot be opened as files",
+                ));
+            }
+        };
+        Ok(VfsFile { contents, pos: Arc::new(AtomicU64::new(0)) })
+    }
+
+    pub fn file_attr(&self) -> io::Result<FileAttr> {
+        Ok(attr(Node::File(self.contents)))
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos.load(Relaxed);
+        let n = self.read_at(buf, pos)?;
+        self.pos.store(pos + n as u64, Relaxed);
+        Ok(n)
+    }
+
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(self.contents.len());
+        let rest = &self.contents[start..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        Ok(n)
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => (self.contents.len() as u64).checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.load(Relaxed).checked_add_signed(n),
+        };
+        let new = new.filter(|&n| i64::try_from(n).is_ok()).ok_or_else(|| {
+            io::const_io_error!(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.pos.store(new, Relaxed);
+        Ok(new)
+    }
+
+    pub fn duplicate(&self) -> io::Result<VfsFile> {
+        Ok(VfsFile { contents: self.contents, pos: self.pos.clone() })
+    }
+}
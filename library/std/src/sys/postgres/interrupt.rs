@@ -0,0 +1,71 @@
+//! Noticing query cancels during long-running operations. See
+//! `std::os::postgres::interrupt`.
+
+use crate::os::postgres::interrupt::{Interrupted, Interrupts};
+use crate::sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed};
+use crate::sync::{PoisonError, RwLock};
+
+static INTERRUPTS: RwLock<Option<&'static dyn Interrupts>> = RwLock::new(None);
+
+/// How many calls to `check` go by between asking the host.
+const INTERVAL: u32 = 256;
+
+static COUNTDOWN: AtomicU32 = AtomicU32::new(INTERVAL);
+
+// Set while the host is asked, in case asking it ends up checking again.
+static CHECKING: AtomicBool = AtomicBool::new(false);
+
+pub fn set_interrupts(interrupts: Option<&'static dyn Interrupts>) {
+    *INTERRUPTS.write().unwrap_or_else(PoisonError::into_inner) = interrupts;
+}
+
+/// Returns whether the host has installed its `Interrupts`.
+pub fn installed() -> bool {
+    INTERRUPTS.read().unwrap_or_else(PoisonError::into_inner).is_some()
+}
+
+/// Unwinds with an `Interrupted` payload if the host has an interrupt
+/// pending, asking it only every so often.
+#[inline]
+pub fn check() {
+    if poll() {
+        crate::panic::resume_unwind(Box::new(Interrupted));
+    }
+}
+
+/// Unwinds with an `Interrupted` payload if the host has an interrupt
+/// pending.
+pub fn check_now() {
+    if pending() {
+        crate::panic::resume_unwind(Box::new(Interrupted));
+    }
+}
+
+/// Returns whether the host has an interrupt pending, asking it only every so
+/// often, for callers that must not unwind themselves.
+#[inline]
+pub fn poll() -> bool {
+    if COUNTDOWN.fetch_sub(1, Relaxed) <= 1 {
+        COUNTDOWN.store(INTERVAL, Relaxed);
+        return pending();
+    }
+    false
+}
+
+/// Returns whether the host has an interrupt pending that can be acted on.
+fn pending() -> bool {
+    // Unwinding again from code that runs during unwinding would abort.
+    if crate::thread::panicking() || CHECKING.swap(true, Relaxed) {
+        return false;
+    }
+    // Cleared before returning, and if the host panics.
+    struct Checking;
+    impl Drop for Checking {
+        fn drop(&mut self) {
+            CHECKING.store(false, Relaxed);
+        }
+    }
+    let _checking = Checking;
+    let interrupts = *INTERRUPTS.read().unwrap_or_else(PoisonError::into_inner);
+    interrupts.map_or(false, |interrupts| interrupts.pending())
+}
@@ -1,5 +1,4 @@
 use crate::mem;
-use crate::os::fd::AsFd;
 
 #[derive(Copy, Clone)]
 pub struct IoSlice<'a>(&'a [u8]);
@@ -47,7 +46,7 @@ impl<'a> IoSliceMut<'a> {
     }
 }
 
-pub fn is_terminal(fd: &impl AsFd) -> bool {
+pub fn is_terminal<T: ?Sized>(_handle: &T) -> bool {
     // No? I mean, I guess we could answer yes if we detect psql?
     false
 }
@@ -1,6 +1,9 @@
 use crate::sys::locks::Mutex;
 use crate::time::Duration;
 
+// How long `wait` naps, so that waiting in a loop doesn't spin.
+const NAP: Duration = Duration::from_millis(1);
+
 pub struct Condvar {}
 
 pub type MovableCondvar = Condvar;
@@ -18,11 +21,31 @@ impl Condvar {
     #[inline]
     pub fn notify_all(&self) {}
 
+    // There is no other thread to notify us, so blocking would never end.
+    // Instead this naps and wakes up spuriously, which callers must expect,
+    // but first unwinds if a cancel or the statement timeout is pending, so
+    // that the loop the caller waits in can end.
     pub unsafe fn wait(&self, _mutex: &Mutex) {
-        panic!("condvar wait not supported")
+        if !crate::sys::interrupt::installed() {
+            // Panics on purpose: without the host's interrupts, nothing could
+            // ever end the wait.
+            panic!("condvar wait not supported without the host's interrupts");
+        }
+        crate::thread::sleep(NAP);
+        crate::sys::interrupt::check_now();
     }
 
-    pub unsafe fn wait_timeout(&self, _mutex: &Mutex, _dur: Duration) -> bool {
-        panic!("condvar wait not supported");
+    // Nothing can notify us here either, so this always times out. It naps
+    // in steps rather than sleeping `dur` through, so that a cancel ends a
+    // long wait as soon as it would end `wait`.
+    pub unsafe fn wait_timeout(&self, _mutex: &Mutex, dur: Duration) -> bool {
+        let mut left = dur;
+        while !left.is_zero() {
+            let nap = left.min(NAP);
+            crate::thread::sleep(nap);
+            crate::sys::interrupt::check_now();
+            left -= nap;
+        }
+        false
     }
 }
@@ -20,6 +20,8 @@ impl Mutex {
 
     #[inline]
     pub unsafe fn lock(&self) {
+        // Panics on purpose: with a single thread, locking a mutex this thread
+        // already holds would otherwise wait forever.
         assert_eq!(self.locked.replace(true), false, "cannot recursively acquire mutex");
     }
 
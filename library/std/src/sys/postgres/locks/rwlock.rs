@@ -60,6 +60,6 @@ impl RwLock {
 
     #[inline]
     pub unsafe fn write_unlock(&self) {
-        assert_eq!(self.mode.replace(0), -1);
+        self.mode.set(0);
     }
 }
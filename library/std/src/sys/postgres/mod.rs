@@ -9,6 +9,7 @@ pub mod args;
 pub mod cmath;
 pub mod env;
 pub mod fs;
+pub mod interrupt;
 pub mod io;
 pub mod locks;
 pub mod net;
@@ -19,6 +20,8 @@ pub mod os_str;
 pub mod path;
 pub mod pipe;
 pub mod process;
+pub mod rand;
+pub mod replay;
 pub mod stdio;
 pub mod thread;
 #[cfg(target_thread_local)]
@@ -80,6 +83,27 @@ where
     }
 }
 
+// Like `cvt`, but keeps the real errno. Only for syscalls on something the host
+// has already let code reach, like an allowlisted socket or directory, where the
+// error gives nothing away and "connection refused" or "not found" is much more
+// useful than "unsupported".
+pub fn cvt_real<T: IsMinusOne>(t: T) -> crate::io::Result<T> {
+    if t.is_minus_one() { Err(real_last_os_error_use_carefully()) } else { Ok(t) }
+}
+
+pub fn cvt_real_r<T, F>(mut f: F) -> crate::io::Result<T>
+where
+    T: IsMinusOne,
+    F: FnMut() -> T,
+{
+    loop {
+        match cvt_real(f()) {
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            other => return other,
+        }
+    }
+}
+
 // #[allow(dead_code)] // Not used on all platforms.
 // pub fn cvt_nz(error: libc::c_int) -> crate::io::Result<()> {
 //     if error == 0 {
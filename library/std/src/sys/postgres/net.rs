@@ -10,8 +10,11 @@ use crate::cmp;
 use crate::ffi::CStr;
 use crate::mem;
 use crate::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, RawFd};
+use crate::path::{Path, PathBuf};
 use crate::str;
+use crate::sync::{PoisonError, RwLock};
 use crate::sys::fd::FileDesc;
+use crate::sys::{cvt_real, cvt_real_r};
 use crate::sys_common::{AsInner, FromInner, IntoInner};
 use crate::time::{Duration, Instant};
 
@@ -19,7 +22,10 @@ use core::ffi::c_void;
 pub(crate) use libc as netc;
 use libc::{c_int, sockaddr, socklen_t};
 
-pub struct TcpStream(!);
+// The TCP and UDP types can only be made by rewrapping the descriptor of an
+// allowlisted Unix socket (`From<OwnedFd>`), so they hold on to it without ever
+// doing anything with it.
+pub struct TcpStream(Socket);
 
 impl TcpStream {
     pub fn connect(_: io::Result<&SocketAddr>) -> io::Result<TcpStream> {
@@ -31,7 +37,7 @@ impl TcpStream {
     }
 
     pub fn socket(&self) -> &Socket {
-        self.0
+        &self.0
     }
 
     pub fn into_socket(self) -> Socket {
@@ -39,99 +45,99 @@ impl TcpStream {
     }
 
     pub fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
-        self.0
+        unsupported()
     }
 
     pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
-        self.0
+        unsupported()
     }
 
     pub fn peek(&self, _: &mut [u8]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn read(&self, _: &mut [u8]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn read_buf(&self, buf: crate::io::BorrowedCursor<'_>) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn read_vectored(&self, _: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn is_read_vectored(&self) -> bool {
-        self.0
+        false
     }
 
     pub fn write(&self, _: &[u8]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn write_vectored(&self, _: &[IoSlice<'_>]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn is_write_vectored(&self) -> bool {
-        self.0
+        false
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.0
+        unsupported()
     }
 
     pub fn socket_addr(&self) -> io::Result<SocketAddr> {
-        self.0
+        unsupported()
     }
 
     pub fn shutdown(&self, _: Shutdown) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn duplicate(&self) -> io::Result<TcpStream> {
-        self.0
+        unsupported()
     }
 
     pub fn set_linger(&self, _: Option<Duration>) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn linger(&self) -> io::Result<Option<Duration>> {
-        self.0
+        unsupported()
     }
 
     pub fn set_nodelay(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn nodelay(&self) -> io::Result<bool> {
-        self.0
+        unsupported()
     }
 
     pub fn set_ttl(&self, _: u32) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn ttl(&self) -> io::Result<u32> {
-        self.0
+        unsupported()
     }
 
     pub fn take_error(&self) -> io::Result<Option<io::Error>> {
-        self.0
+        unsupported()
     }
 
     pub fn set_nonblocking(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 }
 
@@ -141,7 +147,7 @@ impl fmt::Debug for TcpStream {
     }
 }
 
-pub struct TcpListener(!);
+pub struct TcpListener(Socket);
 
 impl TcpListener {
     pub fn bind(_: io::Result<&SocketAddr>) -> io::Result<TcpListener> {
@@ -149,7 +155,7 @@ impl TcpListener {
     }
 
     pub fn socket(&self) -> &Socket {
-        self.0
+        &self.0
     }
 
     pub fn into_socket(self) -> Socket {
@@ -157,39 +163,39 @@ impl TcpListener {
     }
 
     pub fn socket_addr(&self) -> io::Result<SocketAddr> {
-        self.0
+        unsupported()
     }
 
     pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
-        self.0
+        unsupported()
     }
 
     pub fn duplicate(&self) -> io::Result<TcpListener> {
-        self.0
+        unsupported()
     }
 
     pub fn set_ttl(&self, _: u32) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn ttl(&self) -> io::Result<u32> {
-        self.0
+        unsupported()
     }
 
     pub fn set_only_v6(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn only_v6(&self) -> io::Result<bool> {
-        self.0
+        unsupported()
     }
 
     pub fn take_error(&self) -> io::Result<Option<io::Error>> {
-        self.0
+        unsupported()
     }
 
     pub fn set_nonblocking(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 }
 
@@ -199,7 +205,7 @@ impl fmt::Debug for TcpListener {
     }
 }
 
-pub struct UdpSocket(!);
+pub struct UdpSocket(Socket);
 
 impl UdpSocket {
     pub fn bind(_: io::Result<&SocketAddr>) -> io::Result<UdpSocket> {
@@ -207,7 +213,7 @@ impl UdpSocket {
     }
 
     pub fn socket(&self) -> &Socket {
-        self.0
+        &self.0
     }
 
     pub fn into_socket(self) -> Socket {
@@ -215,129 +221,129 @@ impl UdpSocket {
     }
 
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.0
+        unsupported()
     }
 
     pub fn socket_addr(&self) -> io::Result<SocketAddr> {
-        self.0
+        unsupported()
     }
 
     pub fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
-        self.0
+        unsupported()
     }
 
     pub fn peek_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
-        self.0
+        unsupported()
     }
 
     pub fn send_to(&self, _: &[u8], _: &SocketAddr) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn duplicate(&self) -> io::Result<UdpSocket> {
-        self.0
+        unsupported()
     }
 
     pub fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
-        self.0
+        unsupported()
     }
 
     pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
-        self.0
+        unsupported()
     }
 
     pub fn set_broadcast(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn broadcast(&self) -> io::Result<bool> {
-        self.0
+        unsupported()
     }
 
     pub fn set_multicast_loop_v4(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn multicast_loop_v4(&self) -> io::Result<bool> {
-        self.0
+        unsupported()
     }
 
     pub fn set_multicast_ttl_v4(&self, _: u32) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn multicast_ttl_v4(&self) -> io::Result<u32> {
-        self.0
+        unsupported()
     }
 
     pub fn set_multicast_loop_v6(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn multicast_loop_v6(&self) -> io::Result<bool> {
-        self.0
+        unsupported()
     }
 
     pub fn join_multicast_v4(&self, _: &Ipv4Addr, _: &Ipv4Addr) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn join_multicast_v6(&self, _: &Ipv6Addr, _: u32) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn leave_multicast_v4(&self, _: &Ipv4Addr, _: &Ipv4Addr) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn leave_multicast_v6(&self, _: &Ipv6Addr, _: u32) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn set_ttl(&self, _: u32) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn ttl(&self) -> io::Result<u32> {
-        self.0
+        unsupported()
     }
 
     pub fn take_error(&self) -> io::Result<Option<io::Error>> {
-        self.0
+        unsupported()
     }
 
     pub fn set_nonblocking(&self, _: bool) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 
     pub fn recv(&self, _: &mut [u8]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn peek(&self, _: &mut [u8]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn send(&self, _: &[u8]) -> io::Result<usize> {
-        self.0
+        unsupported()
     }
 
     pub fn connect(&self, _: io::Result<&SocketAddr>) -> io::Result<()> {
-        self.0
+        unsupported()
     }
 }
 
 impl fmt::Debug for UdpSocket {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpSocket").finish_non_exhaustive()
     }
 }
 
@@ -372,7 +378,91 @@ impl<'a> TryFrom<(&'a str, u16)> for LookupHost {
     }
 }
 
-pub struct Socket(!);
+// The only sockets that can be created are Unix-domain stream connections to
+// paths the host has allowlisted (see `Socket::connect_unix`). Everything else
+// still reports `Unsupported`, including the ancillary data APIs, so no file
+// descriptors or credentials can be passed over a connection.
+pub struct Socket(FileDesc);
+
+static UNIX_SOCKET_ALLOWLIST: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+pub fn set_unix_socket_allowlist(paths: Vec<PathBuf>) {
+    let old = mem::replace(
+        &mut *UNIX_SOCKET_ALLOWLIST.write().unwrap_or_else(PoisonError::into_inner),
+        paths,
+    );
+    drop(old);
+}
+
+fn unix_socket_allowed(path: &Path) -> bool {
+    // Compare the raw bytes rather than `Path`s: `Path`'s equality normalizes
+    // some components away, and we want an exact match.
+    let allowlist = UNIX_SOCKET_ALLOWLIST.read().unwrap_or_else(PoisonError::into_inner);
+    allowlist.iter().any(|allowed| allowed.as_os_str().as_bytes() == path.as_os_str().as_bytes())
+}
+
+fn setsockopt<T>(
+    sock: &Socket,
+    level: c_int,
+    option_name: c_int,
+    option_value: T,
+) -> io::Result<()> {
+    unsafe {
+        cvt_real(libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            option_name,
+            &option_value as *const T as *const _,
+            mem::size_of::<T>() as socklen_t,
+        ))?;
+        Ok(())
+    }
+}
+
+fn getsockopt<T: Copy>(sock: &Socket, level: c_int, option_name: c_int) -> io::Result<T> {
+    unsafe {
+        let mut option_value: T = mem::zeroed();
+        let mut option_len = mem::size_of::<T>() as socklen_t;
+        cvt_real(libc::getsockopt(
+            sock.as_raw_fd(),
+            level,
+            option_name,
+            &mut option_value as *mut T as *mut _,
+            &mut option_len,
+        ))?;
+        Ok(option_value)
+    }
+}
+
+fn sockaddr_un(path: &Path) -> io::Result<(libc::sockaddr_un, socklen_t)> {
+    // SAFETY: All zeros is a valid representation for `sockaddr_un`.
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.contains(&0) {
+        return Err(io::const_io_error!(
+            io::ErrorKind::InvalidInput,
+            "paths must not contain interior null bytes",
+        ));
+    }
+    // Leave room for the trailing nul; unlike the unix implementation we never
+    // accept (or produce) abstract or unnamed addresses.
+    if bytes.is_empty() || bytes.len() >= addr.sun_path.len() {
+        return Err(io::const_io_error!(
+            io::ErrorKind::InvalidInput,
+            "path must be shorter than SUN_LEN",
+        ));
+    }
+    for (dst, src) in crate::iter::zip(&mut addr.sun_path, bytes) {
+        *dst = *src as libc::c_char;
+    }
+
+    let base = &addr as *const _ as usize;
+    let path = &addr.sun_path as *const _ as usize;
+    let len = path - base + bytes.len() + 1;
+    Ok((addr, len as socklen_t))
+}
 
 pub fn init() {}
 
@@ -393,6 +483,49 @@ impl Socket {
         unsupported()
     }
 
+    /// Connects a Unix-domain stream socket to `path`, if the host has put
+    /// exactly that path on the allowlist.
+    pub fn connect_unix(path: &Path) -> io::Result<Socket> {
+        if !unix_socket_allowed(path) {
+            return Err(io::const_io_error!(
+                io::ErrorKind::PermissionDenied,
+                "socket path is not on the allowlist",
+            ));
+        }
+        let (addr, len) = sockaddr_un(path)?;
+        let socket = Socket::new_unix_stream()?;
+        cvt_real(unsafe {
+            libc::connect(socket.as_raw_fd(), &addr as *const _ as *const _, len)
+        })?;
+        Ok(socket)
+    }
+
+    fn new_unix_stream() -> io::Result<Socket> {
+        unsafe {
+            cfg_if::cfg_if! {
+                if #[cfg(target_os = "linux")] {
+                    let fd = cvt_real(libc::socket(
+                        libc::AF_UNIX,
+                        libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                        0,
+                    ))?;
+                    Ok(Socket(FileDesc::from_raw_fd(fd)))
+                } else {
+                    let fd = cvt_real(libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0))?;
+                    let socket = Socket(FileDesc::from_raw_fd(fd));
+                    cvt_real(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC))?;
+
+                    // macOS uses `SO_NOSIGPIPE` as a `setsockopt` flag to
+                    // disable `SIGPIPE` emission on socket.
+                    #[cfg(target_vendor = "apple")]
+                    setsockopt(&socket, libc::SOL_SOCKET, libc::SO_NOSIGPIPE, 1)?;
+
+                    Ok(socket)
+                }
+            }
+        }
+    }
+
     pub fn connect_timeout(&self, addr: &SocketAddr, timeout: Duration) -> io::Result<()> {
         unsupported()
     }
@@ -402,23 +535,28 @@ impl Socket {
     }
 
     pub fn duplicate(&self) -> io::Result<Socket> {
-        unsupported()
+        self.0.duplicate().map(Socket)
     }
 
     fn recv_with_flags(&self, buf: &mut [u8], flags: c_int) -> io::Result<usize> {
-        unsupported()
+        let ret = cvt_real_r(|| unsafe {
+            libc::recv(self.as_raw_fd(), buf.as_mut_ptr() as *mut c_void, buf.len(), flags)
+        })?;
+        Ok(ret as usize)
     }
 
     pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
-        unsupported()
+        self.recv_with_flags(buf, 0)
     }
 
     pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
-        unsupported()
+        self.recv_with_flags(buf, libc::MSG_PEEK)
     }
 
     pub fn read_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
-        unsupported()
+        // Our `IoSliceMut` isn't layout-compatible with `iovec`, so there is no
+        // `readv` here.
+        io::default_read_vectored(|buf| self.read(buf), bufs)
     }
 
     #[inline]
@@ -448,11 +586,18 @@ impl Socket {
     }
 
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
-        unsupported()
+        #[cfg(target_os = "linux")]
+        let flags = libc::MSG_NOSIGNAL;
+        #[cfg(not(target_os = "linux"))]
+        let flags = 0;
+        let ret = cvt_real_r(|| unsafe {
+            libc::send(self.as_raw_fd(), buf.as_ptr() as *const c_void, buf.len(), flags)
+        })?;
+        Ok(ret as usize)
     }
 
     pub fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
-        unsupported()
+        io::default_write_vectored(|buf| self.write(buf), bufs)
     }
 
     #[inline]
@@ -466,15 +611,53 @@ impl Socket {
     }
 
     pub fn set_timeout(&self, dur: Option<Duration>, kind: c_int) -> io::Result<()> {
-        unsupported()
+        let timeout = match dur {
+            Some(dur) => {
+                if dur.as_secs() == 0 && dur.subsec_nanos() == 0 {
+                    return Err(io::const_io_error!(
+                        io::ErrorKind::InvalidInput,
+                        "cannot set a 0 duration timeout",
+                    ));
+                }
+
+                let secs = if dur.as_secs() > libc::time_t::MAX as u64 {
+                    libc::time_t::MAX
+                } else {
+                    dur.as_secs() as libc::time_t
+                };
+                let mut timeout = libc::timeval {
+                    tv_sec: secs,
+                    tv_usec: dur.subsec_micros() as libc::suseconds_t,
+                };
+                if timeout.tv_sec == 0 && timeout.tv_usec == 0 {
+                    timeout.tv_usec = 1;
+                }
+                timeout
+            }
+            None => libc::timeval { tv_sec: 0, tv_usec: 0 },
+        };
+        setsockopt(self, libc::SOL_SOCKET, kind, timeout)
     }
 
     pub fn timeout(&self, kind: libc::c_int) -> io::Result<Option<Duration>> {
-        unsupported()
+        let raw: libc::timeval = getsockopt(self, libc::SOL_SOCKET, kind)?;
+        if raw.tv_sec == 0 && raw.tv_usec == 0 {
+            Ok(None)
+        } else {
+            let sec = raw.tv_sec as u64;
+            let nsec = (raw.tv_usec as u32) * 1000;
+            Ok(Some(Duration::new(sec, nsec)))
+        }
     }
 
     pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
-        unsupported()
+        let how = match how {
+            Shutdown::Write => libc::SHUT_WR,
+            Shutdown::Read => libc::SHUT_RD,
+            Shutdown::Both => libc::SHUT_RDWR,
+        };
+        cvt_real(unsafe { libc::shutdown(self.as_raw_fd(), how) })?;
+        Ok(())
     }
 
     pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
@@ -523,14 +706,10 @@ impl Socket {
         unsupported()
     }
 
-    #[cfg(not(any(target_os = "solaris", target_os = "illumos")))]
     pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
-        unsupported()
-    }
-
-    #[cfg(any(target_os = "solaris", target_os = "illumos"))]
-    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
-        unsupported()
+        let mut nonblocking = nonblocking as c_int;
+        cvt_real(unsafe { libc::ioctl(self.as_raw_fd(), libc::FIONBIO, &mut nonblocking) })
+            .map(drop)
     }
 
     #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "openbsd"))]
@@ -539,66 +718,69 @@ impl Socket {
     }
 
     pub fn take_error(&self) -> io::Result<Option<io::Error>> {
-        unsupported()
+        let raw: c_int = getsockopt(self, libc::SOL_SOCKET, libc::SO_ERROR)?;
+        if raw == 0 { Ok(None) } else { Ok(Some(io::Error::from_raw_os_error(raw as i32))) }
     }
 
     // This is used by sys_common code to abstract over Windows and Unix.
     pub fn as_raw(&self) -> RawFd {
-        unimplemented!()
+        self.as_raw_fd()
     }
 }
 
 impl AsInner<FileDesc> for Socket {
+    #[inline]
     fn as_inner(&self) -> &FileDesc {
-        unimplemented!()
+        &self.0
     }
 }
 
 impl IntoInner<FileDesc> for Socket {
     fn into_inner(self) -> FileDesc {
-        unimplemented!()
+        self.0
     }
 }
 
 impl FromInner<FileDesc> for Socket {
     fn from_inner(file_desc: FileDesc) -> Self {
-        unimplemented!()
+        Self(file_desc)
     }
 }
 
 impl AsFd for Socket {
     fn as_fd(&self) -> BorrowedFd<'_> {
-        unimplemented!()
+        self.0.as_fd()
     }
 }
 
 impl AsRawFd for Socket {
+    #[inline]
     fn as_raw_fd(&self) -> RawFd {
-        unimplemented!()
+        self.0.as_raw_fd()
     }
 }
 
 impl IntoRawFd for Socket {
     fn into_raw_fd(self) -> RawFd {
-        unimplemented!()
+        self.0.into_raw_fd()
     }
 }
 
 impl FromRawFd for Socket {
     unsafe fn from_raw_fd(raw_fd: RawFd) -> Self {
-        unimplemented!()
+        Self(FromRawFd::from_raw_fd(raw_fd))
     }
 }
 
 impl FromInner<Socket> for TcpListener {
     fn from_inner(socket: Socket) -> TcpListener {
-        unimplemented!()
+        TcpListener(socket)
     }
 }
 
 impl FromInner<Socket> for UdpSocket {
     fn from_inner(socket: Socket) -> UdpSocket {
-        unimplemented!()
+        UdpSocket(socket)
     }
 }
 
@@ -633,12 +815,12 @@ impl<'a> IntoInner<(SocketAddrCRepr, socklen_t)> for &'a SocketAddr {
 
 impl AsInner<Socket> for TcpStream {
     fn as_inner(&self) -> &Socket {
-        self.0
+        &self.0
     }
 }
 
 impl FromInner<Socket> for TcpStream {
     fn from_inner(socket: Socket) -> TcpStream {
-        socket.0
+        TcpStream(socket)
     }
 }
@@ -1,11 +1,23 @@
+#[cfg(test)]
+mod tests;
+
 use super::unsupported;
+use crate::collections::BTreeMap;
 use crate::error::Error as StdError;
 use crate::ffi::{c_char, c_int, c_void};
 use crate::ffi::{OsStr, OsString};
 use crate::fmt;
+#[allow(deprecated)]
+use crate::hash::{Hash, Hasher, SipHasher13};
 use crate::io;
-use crate::marker::PhantomData;
+use crate::iter;
+use crate::os::unix::ffi::OsStrExt;
 use crate::path::{self, PathBuf};
+use crate::slice;
+use crate::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use crate::sync::{PoisonError, RwLock};
+use crate::sys::rand;
+use crate::vec;
 use libc;
 // snarfed from sys/unix/os.rs, which is largely not something we can use
 extern "C" {
@@ -57,23 +69,42 @@ pub fn error_string(_errno: i32) -> String {
 }
 
 pub fn getcwd() -> io::Result<PathBuf> {
-    unsupported()
+    // Only ever a mounted directory, see `fs::vfs`.
+    crate::sys::fs::getcwd()
 }
 
-pub fn chdir(_: &path::Path) -> io::Result<()> {
-    unsupported()
+pub fn chdir(p: &path::Path) -> io::Result<()> {
+    crate::sys::fs::chdir(p)
 }
 
-pub struct SplitPaths<'a>(!, PhantomData<&'a ()>);
+pub struct SplitPaths<'a> {
+    iter: iter::Map<slice::Split<'a, u8, fn(&u8) -> bool>, fn(&'a [u8]) -> PathBuf>,
+}
 
-pub fn split_paths(_unparsed: &OsStr) -> SplitPaths<'_> {
-    panic!("unsupported")
+// Split the Unix way, although the virtual environment is about the only
+// place a list of paths can come from.
+pub fn split_paths(unparsed: &OsStr) -> SplitPaths<'_> {
+    fn bytes_to_path(b: &[u8]) -> PathBuf {
+        PathBuf::from(<OsStr as OsStrExt>::from_bytes(b))
+    }
+    fn is_separator(b: &u8) -> bool {
+        *b == b':'
+    }
+    let unparsed = unparsed.as_bytes();
+    SplitPaths {
+        iter: unparsed
+            .split(is_separator as fn(&u8) -> bool)
+            .map(bytes_to_path as fn(&[u8]) -> PathBuf),
+    }
 }
 
 impl<'a> Iterator for SplitPaths<'a> {
     type Item = PathBuf;
     fn next(&mut self) -> Option<PathBuf> {
-        self.0
+        self.iter.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
     }
 }
 
@@ -105,21 +136,52 @@ pub fn current_exe() -> io::Result<PathBuf> {
     unsupported()
 }
 
-pub struct Env(!);
+// The process environment is never read. What `std::env` sees instead is
+// whatever the host installs through `std::os::postgres::env`.
+pub enum Environment {
+    Empty,
+    Vars(BTreeMap<OsString, OsString>),
+    Host(&'static dyn crate::os::postgres::env::Environment),
+}
+
+static ENVIRONMENT: RwLock<Environment> = RwLock::new(Environment::Empty);
+
+pub fn set_environment(env: Environment) {
+    *ENVIRONMENT.write().unwrap_or_else(PoisonError::into_inner) = env;
+}
+
+pub struct Env {
+    iter: vec::IntoIter<(OsString, OsString)>,
+}
+
+impl !Send for Env {}
+impl !Sync for Env {}
 
 impl Iterator for Env {
     type Item = (OsString, OsString);
     fn next(&mut self) -> Option<(OsString, OsString)> {
-        self.0
+        self.iter.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
     }
 }
 
 pub fn env() -> Env {
-    panic!("not supported on this platform")
+    let vars = match &*ENVIRONMENT.read().unwrap_or_else(PoisonError::into_inner) {
+        Environment::Empty => Vec::new(),
+        Environment::Vars(vars) => vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        Environment::Host(env) => env.vars(),
+    };
+    Env { iter: vars.into_iter() }
 }
 
-pub fn getenv(_: &OsStr) -> Option<OsString> {
-    None
+pub fn getenv(k: &OsStr) -> Option<OsString> {
+    match &*ENVIRONMENT.read().unwrap_or_else(PoisonError::into_inner) {
+        Environment::Empty => None,
+        Environment::Vars(vars) => vars.get(k).cloned(),
+        Environment::Host(env) => env.get(k),
+    }
 }
 
 pub fn setenv(_: &OsStr, _: &OsStr) -> io::Result<()> {
@@ -131,23 +193,63 @@ pub fn unsetenv(_: &OsStr) -> io::Result<()> {
 }
 
 pub fn temp_dir() -> PathBuf {
-    panic!("no filesystem on this platform")
+    // A scratch directory backed by Postgres temporary files, see `fs::scratch`.
+    crate::sys::fs::scratch_dir()
 }
 
 pub fn home_dir() -> Option<PathBuf> {
     None
 }
 
-pub fn exit(_code: i32) -> ! {
-    panic!("not allowed to exit")
+pub fn exit(code: i32) -> ! {
+    // Ending the backend would end the session with it. See
+    // `std::os::postgres::process`.
+    crate::panic::resume_unwind(Box::new(crate::os::postgres::process::Exit::new(code)))
 }
 
+// The backend's pid stands in for the session in plenty of crates that make
+// up unique names or ids, but it is not the function's to know: it is how
+// other sessions, and `pg_terminate_backend`, tell this one apart. So the id
+// handed out is a hash of it under a key no one else has, which is all but
+// certainly unique among the running backends, and tells nothing about it.
+//
+// The id is cached along with the pid it was made from, in the upper half, so
+// that a backend forked from a postmaster that already made one gets its own.
+// No process has pid 0, so the initial value is never taken for a cached id.
+static PROCESS_ID: AtomicU64 = AtomicU64::new(0);
+
 pub fn getpid() -> u32 {
-    panic!("no pids here")
+    let pid = unsafe { libc::getpid() } as u32;
+    let cached = PROCESS_ID.load(Relaxed);
+    if (cached >> 32) as u32 == pid {
+        return cached as u32;
+    }
+    let id = process_id(pid);
+    PROCESS_ID.store((pid as u64) << 32 | id as u64, Relaxed);
+    id
+}
+
+fn process_id(pid: u32) -> u32 {
+    let (mut k0, mut k1) = ([0; 8], [0; 8]);
+    // Not through the replay log, which would keep the key in the recording
+    // and hand it to every backend replaying it.
+    let keys =
+        rand::fill_bytes_unrecorded(&mut k0).and_then(|()| rand::fill_bytes_unrecorded(&mut k1));
+    if keys.is_err() {
+        // Without a key, every backend gets the same id.
+        return 1;
+    }
+    #[allow(deprecated)]
+    let mut hasher = SipHasher13::new_with_keys(u64::from_ne_bytes(k0), u64::from_ne_bytes(k1));
+    pid.hash(&mut hasher);
+    // A positive `pid_t`, since that is what most code takes it to be.
+    (hasher.finish() as u32 & i32::MAX as u32).max(1)
 }
 
+// The postmaster's pid is one process for every backend, which no per-backend
+// hash would keep, so it is simply hidden.
 pub fn getppid() -> u32 {
-    panic!("no pids here")
+    0
 }
 
 pub fn page_size() -> usize {
@@ -0,0 +1,22 @@
+use super::split_paths;
+use crate::ffi::OsStr;
+use crate::path::PathBuf;
+
+fn split(unparsed: &str) -> Vec<PathBuf> {
+    split_paths(OsStr::new(unparsed)).collect()
+}
+
+#[test]
+fn split_paths_on_colons() {
+    assert_eq!(split("/bin:/usr/bin"), [PathBuf::from("/bin"), PathBuf::from("/usr/bin")]);
+    assert_eq!(split("/bin"), [PathBuf::from("/bin")]);
+}
+
+#[test]
+fn split_paths_keeps_empty_entries() {
+    assert_eq!(split(""), [PathBuf::new()]);
+    assert_eq!(
+        split(":/bin::"),
+        [PathBuf::new(), PathBuf::from("/bin"), PathBuf::new(), PathBuf::new()]
+    );
+}
@@ -1,6 +1,6 @@
 use crate::io::{self, IoSlice, IoSliceMut};
 use crate::mem;
-use crate::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, RawFd};
+use crate::os::unix::io::{AsFd, AsRawFd, BorrowedFd, IntoRawFd, RawFd};
 use crate::sys::fd::FileDesc;
 use crate::sys::unsupported;
 use crate::sys_common::IntoInner;
@@ -77,9 +77,3 @@ impl IntoRawFd for AnonPipe {
         self.0
     }
 }
-
-impl FromRawFd for AnonPipe {
-    unsafe fn from_raw_fd(raw_fd: RawFd) -> Self {
-        panic!("unavailable")
-    }
-}
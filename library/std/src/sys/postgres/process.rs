@@ -1,6 +1,6 @@
 #![unstable(feature = "postgrestd", issue = "none")]
 use crate::collections::BTreeMap;
-use crate::ffi::{CStr, CString, OsStr, OsString};
+use crate::ffi::{CString, OsStr, OsString};
 use crate::fmt;
 use crate::io;
 use crate::marker::PhantomData;
@@ -31,6 +31,7 @@ use crate::sys_common::IntoInner;
 
 #[derive(Default)]
 pub struct Command {
+    program: OsString,
     cwd: Option<CString>,
 
     env: CommandEnv,
@@ -140,8 +141,9 @@ pub enum Stdio {
 }
 
 impl Command {
-    pub fn new(_program: &OsStr) -> Command {
-        Command::default()
+    pub fn new(program: &OsStr) -> Command {
+        // Kept only to be handed back: nothing is ever run.
+        Command { program: program.to_owned(), ..Command::default() }
     }
 
     pub fn arg(&mut self, _arg: &OsStr) {}
@@ -155,7 +157,7 @@ impl Command {
     pub fn cwd(&mut self, _dir: &OsStr) {}
 
     pub fn get_program(&self) -> &OsStr {
-        panic!("unsupported")
+        &self.program
     }
 
     pub fn get_args(&self) -> CommandArgs<'_> {
@@ -211,14 +213,6 @@ impl Command {
         self.saw_nul
     }
 
-    pub fn get_argv(&self) -> &Vec<*const c_char> {
-        panic!("no meaningful argv")
-    }
-
-    pub fn get_program_cstr(&self) -> &CStr {
-        panic!("no meaningful cstr")
-    }
-
     #[allow(dead_code)]
     pub fn get_cwd(&self) -> &Option<CString> {
         &self.cwd
@@ -285,7 +279,9 @@ impl From<AnonPipe> for Stdio {
 
 impl From<File> for Stdio {
     fn from(file: File) -> Stdio {
-        Stdio::Fd(file.into_inner())
+        // Nothing is ever spawned, so a file without a descriptor can stand
+        // for anything.
+        file.into_fd().map_or(Stdio::Null, Stdio::Fd)
     }
 }
 
@@ -408,7 +404,7 @@ impl ExitStatus {
     }
 
     pub fn exit_ok(&self) -> Result<(), ExitStatusError> {
-        Err(ExitStatusError(1.try_into().unwrap()))
+        Err(ExitStatusError(ExitStatus::from(1)))
     }
 
     pub fn code(&self) -> Option<i32> {
@@ -541,7 +537,7 @@ impl fmt::Display for ExitStatus {
 
 impl ExitStatusError {
     pub fn code(self) -> Option<NonZeroI32> {
-        ExitStatus(self.0.into()).code().map(|st| st.try_into().unwrap())
+        self.0.code().and_then(NonZeroI32::new)
     }
 }
 
@@ -0,0 +1,46 @@
+use crate::io;
+use crate::os::postgres::replay::Value;
+use crate::sys::{cvt_real, replay};
+
+extern "C" {
+    // Both glibc (2.25 and later) and macOS (10.12 and later) have this, but
+    // `libc` only declares it for the former.
+    fn getentropy(buf: *mut libc::c_void, buflen: libc::size_t) -> libc::c_int;
+}
+
+/// Fills `v` with random bytes from the kernel.
+pub fn fill_bytes(v: &mut [u8]) -> io::Result<()> {
+    let bytes = replay::observe(
+        || {
+            let mut bytes = vec![0; v.len()];
+            fill_bytes_unrecorded(&mut bytes)?;
+            Ok(bytes)
+        },
+        // A failure is recorded as no bytes, and replayed as a failure.
+        |bytes: &io::Result<Vec<u8>>| match bytes {
+            Ok(bytes) => Value::RandomBytes(bytes.clone()),
+            Err(_) => Value::RandomBytes(Vec::new()),
+        },
+        |value| match value {
+            Value::RandomBytes(bytes) if bytes.len() == v.len() => Ok(Ok(bytes)),
+            Value::RandomBytes(bytes) if bytes.is_empty() => Ok(Err(io::const_io_error!(
+                io::ErrorKind::Other,
+                "no random bytes were available when this was recorded",
+            ))),
+            value => Err(value),
+        },
+    )?;
+    v.copy_from_slice(&bytes);
+    Ok(())
+}
+
+/// Fills `v` with random bytes from the kernel, bypassing the replay log, so
+/// that they are neither recorded nor replayed. This is for keys that must
+/// stay secret, and must differ from one backend to the next.
+pub fn fill_bytes_unrecorded(v: &mut [u8]) -> io::Result<()> {
+    // getentropy(2) permits a maximum buffer size of 256 bytes
+    for s in v.chunks_mut(256) {
+        cvt_real(unsafe { getentropy(s.as_mut_ptr().cast(), s.len()) })?;
+    }
+    Ok(())
+}
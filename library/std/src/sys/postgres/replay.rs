@@ -0,0 +1,66 @@
+//! Records or replays the values this layer hands out that differ from one run
+//! to the next. See `std::os::postgres::replay`.
+
+use crate::os::postgres::replay::{Mode, Value};
+use crate::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use crate::sync::{PoisonError, RwLock};
+
+static MODE: RwLock<Mode> = RwLock::new(Mode::Off);
+
+// Set while the recorder or replayer runs, so that whatever they read from
+// the clock themselves is neither recorded nor replayed.
+static IN_HOOK: AtomicBool = AtomicBool::new(false);
+
+pub fn set_mode(mode: Mode) {
+    *MODE.write().unwrap_or_else(PoisonError::into_inner) = mode;
+}
+
+fn mode() -> Mode {
+    // Copied out, so that the recorder and replayer can read the clock
+    // themselves without finding the lock taken.
+    *MODE.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn is_replaying() -> bool {
+    matches!(mode(), Mode::Replay(_))
+}
+
+/// Returns `live()` and records it, or returns the next value of the replay
+/// instead if `replayed` accepts it.
+pub fn observe<T>(
+    live: impl FnOnce() -> T,
+    record: impl FnOnce(&T) -> Value,
+    replayed: impl FnOnce(Value) -> Result<T, Value>,
+) -> T {
+    let mode = mode();
+    if matches!(mode, Mode::Off) || IN_HOOK.swap(true, Relaxed) {
+        return live();
+    }
+    // Cleared on the way out, even if the recorder or replayer panics.
+    struct Hook;
+    impl Drop for Hook {
+        fn drop(&mut self) {
+            IN_HOOK.store(false, Relaxed);
+        }
+    }
+    let _hook = Hook;
+
+    let found = match mode {
+        Mode::Off => None,
+        Mode::Record(recorder) => {
+            let value = live();
+            recorder.record(&record(&value));
+            return value;
+        }
+        Mode::Replay(replayer) => match replayer.next().map(replayed) {
+            Some(Ok(value)) => return value,
+            Some(Err(found)) => Some((replayer, Some(found))),
+            None => Some((replayer, None)),
+        },
+    };
+    if let Some((replayer, found)) = found {
+        set_mode(Mode::Off);
+        replayer.diverged(found);
+    }
+    live()
+}
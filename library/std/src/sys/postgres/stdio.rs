@@ -1,4 +1,9 @@
+#[cfg(test)]
+mod tests;
+
+use crate::fmt;
 use crate::io;
+use crate::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use crate::sys::unsupported;
 
 pub struct Stdin(());
@@ -56,3 +61,32 @@ pub const STDIN_BUF_SIZE: usize = crate::sys_common::io::DEFAULT_BUF_SIZE;
 pub fn panic_output() -> Option<impl io::Write> {
     None::<Stderr>
 }
+
+// The most output capture buffers are allowed to hold. See
+// `std::os::postgres::io::start_capture`.
+static CAPTURE_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Sets the most output capture buffers may hold, and returns the previous
+/// limit.
+pub fn set_capture_limit(limit: usize) -> usize {
+    CAPTURE_LIMIT.swap(limit, Relaxed)
+}
+
+/// Appends `args` to an output capture buffer, dropping whatever would take
+/// it past the limit.
+pub fn write_capture(buf: &mut Vec<u8>, args: fmt::Arguments<'_>) {
+    struct Bounded<'a>(&'a mut Vec<u8>, usize);
+
+    impl io::Write for Bounded<'_> {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            let room = self.1.saturating_sub(self.0.len());
+            self.0.extend_from_slice(&data[..data.len().min(room)]);
+            Ok(data.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let _ = io::Write::write_fmt(&mut Bounded(buf, CAPTURE_LIMIT.load(Relaxed)), args);
+}
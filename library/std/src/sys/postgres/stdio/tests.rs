@@ -0,0 +1,26 @@
+use super::{set_capture_limit, write_capture};
+
+// The limit is global, so a single test changes it and puts it back.
+#[test]
+fn write_capture_stops_at_limit() {
+    let previous = set_capture_limit(8);
+
+    let mut buf = Vec::new();
+    write_capture(&mut buf, format_args!("{}", "hello"));
+    assert_eq!(buf, b"hello");
+    write_capture(&mut buf, format_args!("{} {}", ",", "world"));
+    assert_eq!(buf, b"hello, w");
+    write_capture(&mut buf, format_args!("more"));
+    assert_eq!(buf, b"hello, w");
+
+    // What is already there past a lower limit stays.
+    set_capture_limit(2);
+    write_capture(&mut buf, format_args!("more"));
+    assert_eq!(buf, b"hello, w");
+
+    set_capture_limit(usize::MAX);
+    write_capture(&mut buf, format_args!("orld"));
+    assert_eq!(buf, b"hello, world");
+
+    set_capture_limit(previous);
+}
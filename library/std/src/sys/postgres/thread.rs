@@ -22,33 +22,51 @@ impl Thread {
     }
 
     pub fn sleep(dur: Duration) {
-        let mut secs = dur.as_secs();
-        let mut nsecs = dur.subsec_nanos() as _;
-
-        // If we're awoken with a signal then the return value will be -1 and
-        // nanosleep will fill in `ts` with the remaining time.
-        unsafe {
-            while secs > 0 || nsecs > 0 {
-                let mut ts = libc::timespec {
-                    tv_sec: cmp::min(libc::time_t::MAX as u64, secs) as libc::time_t,
-                    tv_nsec: nsecs,
-                };
-                secs -= ts.tv_sec as u64;
-                let ts_ptr = &mut ts as *mut _;
-                if libc::nanosleep(ts_ptr, ts_ptr) == -1 {
-                    // `postgres` does not have `errno()`.
-                    assert_eq!(os::real_errno_use_carefully(), libc::EINTR);
-                    secs += ts.tv_sec as u64;
-                    nsecs = ts.tv_nsec;
-                } else {
-                    nsecs = 0;
-                }
+        if crate::sys::replay::is_replaying() {
+            // The clock readings after the sleep are replayed too.
+            return;
+        }
+        // The statement is cancelled at the deadline, so there is no sleeping
+        // past it, and nothing to go back to after it.
+        match crate::sys::time::time_left() {
+            Some(left) if left <= dur => {
+                nanosleep(left);
+                crate::sys::time::deadline_reached();
             }
+            _ => nanosleep(dur),
         }
     }
 
-    pub fn join(self) {
-        unreachable!();
+    // `new` never makes a thread, so there is none to join.
+    pub fn join(self) {}
+}
+
+fn nanosleep(dur: Duration) {
+    let mut secs = dur.as_secs();
+    let mut nsecs = dur.subsec_nanos() as _;
+
+    // If we're awoken with a signal then the return value will be -1 and
+    // nanosleep will fill in `ts` with the remaining time.
+    unsafe {
+        while secs > 0 || nsecs > 0 {
+            let mut ts = libc::timespec {
+                tv_sec: cmp::min(libc::time_t::MAX as u64, secs) as libc::time_t,
+                tv_nsec: nsecs,
+            };
+            secs -= ts.tv_sec as u64;
+            let ts_ptr = &mut ts as *mut _;
+            if libc::nanosleep(ts_ptr, ts_ptr) == -1 {
+                // `postgres` does not have `errno()`. Anything but a
+                // signal means the time can't be slept, so don't.
+                if os::real_errno_use_carefully() != libc::EINTR {
+                    return;
+                }
+                secs += ts.tv_sec as u64;
+                nsecs = ts.tv_nsec;
+            } else {
+                nsecs = 0;
+            }
+        }
     }
 }
 
@@ -78,17 +96,12 @@ unsafe impl Sync for Thread {}
 
 impl Thread {
     pub fn id(&self) -> libc::pthread_t {
-        unimplemented!("unsupported")
+        self.id
     }
     pub fn into_id(self) -> libc::pthread_t {
-        unimplemented!("unsupported")
-    }
-}
-
-#[cfg(target_family = "postgres")]
-impl Drop for Thread {
-    fn drop(&mut self) {
-        unreachable!();
+        let id = self.id;
+        mem::forget(self);
+        id
     }
 }
 
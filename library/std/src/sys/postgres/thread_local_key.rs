@@ -1,26 +1,52 @@
+//! A backend only ever has the one thread, so a key is simply an index into a
+//! single table of values. Destructors never run, like those registered with
+//! `thread_local_dtor`: the thread only ends along with the process.
+
+#[cfg(test)]
+mod tests;
+
+use crate::ptr;
+use crate::sync::{Mutex, PoisonError};
+
+/// An index into `VALUES`, plus one, since zero marks a key not yet created.
 pub type Key = usize;
 
+struct Values(Vec<*mut u8>);
+
+unsafe impl Send for Values {}
+
+static VALUES: Mutex<Values> = Mutex::new(Values(Vec::new()));
+
+fn values() -> crate::sync::MutexGuard<'static, Values> {
+    VALUES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 #[inline]
 pub unsafe fn create(_dtor: Option<unsafe extern "C" fn(*mut u8)>) -> Key {
-    panic!("should not be used on this target");
+    let mut values = values();
+    values.0.push(ptr::null_mut());
+    values.0.len()
 }
 
 #[inline]
-pub unsafe fn set(_key: Key, _value: *mut u8) {
-    panic!("should not be used on this target");
+pub unsafe fn set(key: Key, value: *mut u8) {
+    if let Some(slot) = values().0.get_mut(key.wrapping_sub(1)) {
+        *slot = value;
+    }
 }
 
 #[inline]
-pub unsafe fn get(_key: Key) -> *mut u8 {
-    panic!("should not be used on this target");
+pub unsafe fn get(key: Key) -> *mut u8 {
+    values().0.get(key.wrapping_sub(1)).copied().unwrap_or(ptr::null_mut())
 }
 
 #[inline]
-pub unsafe fn destroy(_key: Key) {
-    panic!("should not be used on this target");
+pub unsafe fn destroy(key: Key) {
+    // Keys are not reused, so this only forgets the value.
+    set(key, ptr::null_mut())
 }
 
 #[inline]
 pub fn requires_synchronized_create() -> bool {
-    panic!("should not be used on this target");
+    false
 }
@@ -0,0 +1,41 @@
+use super::{create, destroy, get, set};
+use crate::ptr;
+
+#[test]
+fn keys_are_distinct_and_start_null() {
+    unsafe {
+        let a = create(None);
+        let b = create(None);
+        assert_ne!(a, 0);
+        assert_ne!(a, b);
+        assert!(get(a).is_null());
+        assert!(get(b).is_null());
+    }
+}
+
+#[test]
+fn set_then_get() {
+    let mut x = 1u8;
+    let mut y = 2u8;
+    unsafe {
+        let a = create(None);
+        let b = create(None);
+        set(a, &mut x);
+        set(b, &mut y);
+        assert_eq!(get(a), &mut x as *mut u8);
+        assert_eq!(get(b), &mut y as *mut u8);
+        destroy(a);
+        assert!(get(a).is_null());
+        assert_eq!(get(b), &mut y as *mut u8);
+        destroy(b);
+    }
+}
+
+#[test]
+fn unknown_keys_read_null() {
+    unsafe {
+        set(0, ptr::NonNull::dangling().as_ptr());
+        assert!(get(0).is_null());
+        assert!(get(usize::MAX).is_null());
+    }
+}
@@ -1,7 +1,16 @@
+#[cfg(test)]
+mod tests;
+
 use crate::fmt;
+#[allow(deprecated)]
+use crate::hash::{Hash, Hasher, SipHasher13};
+use crate::io;
 use crate::mem::MaybeUninit;
+use crate::os::postgres::replay::Value;
 use crate::prelude::rust_2021::*;
+use crate::sync::{Mutex, PoisonError, RwLock};
 use crate::sys::cvt_unsup;
+use crate::sys::{rand, replay};
 use crate::time::Duration;
 
 #[allow(dead_code)]
@@ -145,9 +154,168 @@ pub struct Instant {
     t: Timespec,
 }
 
+// Readings of both clocks are rounded to a resolution the host sets, so that
+// code can't build fine-grained timers out of them to measure other sessions
+// with. See `std::os::postgres::time::set_clock_resolution`.
+struct Coarsening {
+    resolution: i128,
+    // With jitter, each interval of `resolution` rounds up from a point in it
+    // picked by a keyed hash, instead of always rounding down. That still
+    // keeps readings in order, but hides where an interval begins.
+    jitter: Option<(u64, u64)>,
+}
+
+static COARSENING: RwLock<Option<Coarsening>> = RwLock::new(None);
+
+// What the clocks read under the volatility the host declared for the call:
+// under `Stable` the wall clock stays at the start of the statement, and
+// under `Immutable` both clocks stand still. See
+// `std::os::postgres::volatility`.
+#[derive(Clone, Copy)]
+pub enum Volatility {
+    Volatile,
+    Stable(SystemTime),
+    Immutable,
+}
+
+static VOLATILITY: RwLock<Volatility> = RwLock::new(Volatility::Volatile);
+
+pub fn set_volatility(volatility: Volatility) {
+    *VOLATILITY.write().unwrap_or_else(PoisonError::into_inner) = volatility;
+}
+
+pub fn volatility() -> Volatility {
+    *VOLATILITY.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+// When `statement_timeout` cancels the current statement, on the monotonic
+// clock. See `std::os::postgres::time::deadline`.
+static DEADLINE: RwLock<Option<Timespec>> = RwLock::new(None);
+
+pub fn set_statement_timeout(statement_start: SystemTime, timeout: Option<Duration>) {
+    // The statement started by the wall clock, and how long ago that was
+    // carries over to the monotonic clock. A deadline too far off to
+    // represent is as good as none.
+    let deadline = timeout.and_then(|timeout| {
+        let end = statement_start.t.checked_add_duration(&timeout)?;
+        let now = Timespec::now(libc::CLOCK_MONOTONIC);
+        match end.sub_timespec(&Timespec::now(libc::CLOCK_REALTIME)) {
+            Ok(left) => now.checked_add_duration(&left),
+            Err(_) => Some(now),
+        }
+    });
+    *DEADLINE.write().unwrap_or_else(PoisonError::into_inner) = deadline;
+}
+
+pub fn deadline() -> Option<Instant> {
+    // `Instant::now` stands still under `Immutable` and reads back what was
+    // recorded while replaying, so a deadline on the real clock would mean
+    // nothing next to it.
+    if let Volatility::Immutable = volatility() {
+        return None;
+    }
+    if replay::is_replaying() {
+        return None;
+    }
+    // Rounded like `Instant::now`, or it would give away the finer reading
+    // it was worked out from.
+    DEADLINE.read().unwrap_or_else(PoisonError::into_inner).map(|t| Instant { t: coarsen(t) })
+}
+
+/// Returns how long until the deadline, or `None` if there is none.
+///
+/// Unlike `deadline`, this always goes by the real clock, which is the one
+/// `statement_timeout` goes by, since it bounds real waits.
+pub fn time_left() -> Option<Duration> {
+    let deadline = (*DEADLINE.read().unwrap_or_else(PoisonError::into_inner))?;
+    Some(deadline.sub_timespec(&Timespec::now(libc::CLOCK_MONOTONIC)).unwrap_or(Duration::ZERO))
+}
+
+/// Unwinds out of a wait that has reached the deadline: with `Interrupted`
+/// if the host has the cancel pending already, and with `TimedOut` if not.
+/// Does nothing while the thread is already unwinding.
+pub fn deadline_reached() {
+    if crate::thread::panicking() {
+        return;
+    }
+    crate::sys::interrupt::check_now();
+    crate::panic::resume_unwind(Box::new(crate::os::postgres::time::TimedOut))
+}
+
+// The latest `Instant` handed out, so that a change in resolution can't make
+// `Instant::now` go backwards, and neither can the end of a replay.
+static LAST_INSTANT: Mutex<Timespec> = Mutex::new(Timespec::zero());
+
+/// Makes `t` the latest `Instant` handed out, if it is later, and returns the
+/// latest one.
+fn advance_last_instant(t: Timespec) -> Timespec {
+    let mut last = LAST_INSTANT.lock().unwrap_or_else(PoisonError::into_inner);
+    *last = (*last).max(t);
+    *last
+}
+
+pub fn set_resolution(resolution: Duration, jitter: bool) -> io::Result<()> {
+    let coarsening = if resolution.is_zero() {
+        None
+    } else {
+        let jitter = if jitter {
+            let (mut k0, mut k1) = ([0; 8], [0; 8]);
+            rand::fill_bytes(&mut k0)?;
+            rand::fill_bytes(&mut k1)?;
+            Some((u64::from_ne_bytes(k0), u64::from_ne_bytes(k1)))
+        } else {
+            None
+        };
+        // Longer than that, rounding up could carry the seconds past `i64`.
+        let resolution = resolution.as_nanos().min(i64::MAX as u128) as i128;
+        Some(Coarsening { resolution, jitter })
+    };
+    *COARSENING.write().unwrap_or_else(PoisonError::into_inner) = coarsening;
+    Ok(())
+}
+
+fn coarsen(t: Timespec) -> Timespec {
+    let coarsening = COARSENING.read().unwrap_or_else(PoisonError::into_inner);
+    let Some(Coarsening { resolution, jitter }) = *coarsening else { return t };
+    let nanos = t.tv_sec as i128 * NSEC_PER_SEC as i128 + t.tv_nsec as i128;
+    let interval = nanos.div_euclid(resolution);
+    let offset = nanos.rem_euclid(resolution);
+    let round_up = match jitter {
+        Some((k0, k1)) => {
+            #[allow(deprecated)]
+            let mut hasher = SipHasher13::new_with_keys(k0, k1);
+            interval.hash(&mut hasher);
+            offset >= (hasher.finish() as u128 % resolution as u128) as i128
+        }
+        None => false,
+    };
+    let nanos = (interval + round_up as i128) * resolution;
+    Timespec::new(
+        nanos.div_euclid(NSEC_PER_SEC as i128) as i64,
+        nanos.rem_euclid(NSEC_PER_SEC as i128) as i64,
+    )
+}
+
 impl Instant {
     pub fn now() -> Instant {
-        Instant { t: Timespec::now(libc::CLOCK_MONOTONIC) }
+        if let Volatility::Immutable = volatility() {
+            return Instant { t: Timespec::zero() };
+        }
+        let t = replay::observe(
+            || advance_last_instant(coarsen(Timespec::now(libc::CLOCK_MONOTONIC))),
+            |t| Value::Instant { secs: t.tv_sec, nanos: t.tv_nsec as u32 },
+            |value| match value {
+                Value::Instant { secs, nanos } if nanos < NSEC_PER_SEC as u32 => {
+                    // Replayed as recorded, but live readings after the
+                    // replay diverges never go back past it.
+                    let t = Timespec::new(secs, nanos as i64);
+                    advance_last_instant(t);
+                    Ok(t)
+                }
+                value => Err(value),
+            },
+        );
+        Instant { t }
     }
 
     pub fn checked_sub_instant(&self, other: &Instant) -> Option<Duration> {
@@ -174,7 +342,22 @@ impl fmt::Debug for Instant {
 
 impl SystemTime {
     pub fn now() -> SystemTime {
-        panic!("not yet bound against postgres for time")
+        match volatility() {
+            Volatility::Volatile => {}
+            Volatility::Stable(start) => return SystemTime { t: coarsen(start.t) },
+            Volatility::Immutable => return UNIX_EPOCH,
+        }
+        let t = replay::observe(
+            || coarsen(Timespec::now(libc::CLOCK_REALTIME)),
+            |t| Value::SystemTime { secs: t.tv_sec, nanos: t.tv_nsec as u32 },
+            |value| match value {
+                Value::SystemTime { secs, nanos } if nanos < NSEC_PER_SEC as u32 => {
+                    Ok(Timespec::new(secs, nanos as i64))
+                }
+                value => Err(value),
+            },
+        );
+        SystemTime { t }
     }
 }
 
@@ -201,14 +384,19 @@ impl Timespec {
 
             if let Some(clock_gettime64) = __clock_gettime64.get() {
                 let mut t = MaybeUninit::zeroed();
-                cvt_unsup(unsafe { clock_gettime64(clock, t.as_mut_ptr()) }).unwrap();
-                let t = unsafe { t.assume_init() };
-                return Timespec { tv_sec: t.tv_sec, tv_nsec: t.tv_nsec as i64 };
+                if cvt_unsup(unsafe { clock_gettime64(clock, t.as_mut_ptr()) }).is_ok() {
+                    let t = unsafe { t.assume_init() };
+                    return Timespec { tv_sec: t.tv_sec, tv_nsec: t.tv_nsec as i64 };
+                }
             }
         }
 
+        // Only the realtime and monotonic clocks are read, which every system
+        // has. Should reading one fail all the same, it reads as its epoch.
         let mut t = MaybeUninit::zeroed();
-        cvt_unsup(unsafe { libc::clock_gettime(clock, t.as_mut_ptr()) }).unwrap();
-        Timespec::from(unsafe { t.assume_init() })
+        match cvt_unsup(unsafe { libc::clock_gettime(clock, t.as_mut_ptr()) }) {
+            Ok(_) => Timespec::from(unsafe { t.assume_init() }),
+            Err(_) => Timespec::zero(),
+        }
     }
 }
@@ -0,0 +1,55 @@
+use super::{coarsen, set_resolution, set_volatility, Coarsening, Timespec, COARSENING};
+use super::{SystemTime, Volatility, NSEC_PER_SEC};
+use crate::sync::PoisonError;
+use crate::time::Duration;
+
+fn set_coarsening(coarsening: Option<Coarsening>) {
+    *COARSENING.write().unwrap_or_else(PoisonError::into_inner) = coarsening;
+}
+
+fn parts(t: Timespec) -> (i64, i64) {
+    (t.tv_sec, t.tv_nsec)
+}
+
+// The resolution is global, so a single test changes it and puts it back.
+#[test]
+fn coarsen_rounds_to_resolution() {
+    let millis = 1_000_000;
+    let t = Timespec::new(7, 123_456_789);
+
+    set_coarsening(None);
+    assert_eq!(parts(coarsen(t)), (7, 123_456_789));
+
+    set_coarsening(Some(Coarsening { resolution: millis, jitter: None }));
+    assert_eq!(parts(coarsen(t)), (7, 123_000_000));
+    assert_eq!(parts(coarsen(Timespec::new(7, 123_000_000))), (7, 123_000_000));
+
+    // A resolution over a second carries into the seconds.
+    set_coarsening(Some(Coarsening { resolution: 3 * NSEC_PER_SEC as i128, jitter: None }));
+    assert_eq!(parts(coarsen(t)), (6, 0));
+
+    // With jitter, every reading lands on one of the two ends of its
+    // interval, and the same reading always lands on the same one.
+    set_coarsening(Some(Coarsening { resolution: millis, jitter: Some((1, 2)) }));
+    for nanos in (0..NSEC_PER_SEC as i64).step_by(999_983) {
+        let t = Timespec::new(7, nanos);
+        let down = 7 * NSEC_PER_SEC as i64 + nanos - nanos % millis as i64;
+        let (secs, coarse) = parts(coarsen(t));
+        let coarse = secs * NSEC_PER_SEC as i64 + coarse;
+        assert!(coarse == down || coarse == down + millis as i64);
+        assert_eq!(parts(coarsen(t)), parts(coarsen(t)));
+    }
+
+    // So is the statement start a `STABLE` call reads.
+    set_coarsening(Some(Coarsening { resolution: millis, jitter: None }));
+    set_volatility(Volatility::Stable(SystemTime { t }));
+    assert_eq!(parts(SystemTime::now().t), (7, 123_000_000));
+    set_volatility(Volatility::Volatile);
+
+    // A resolution too long to round up by without overflowing is clamped.
+    set_resolution(Duration::MAX, false).unwrap();
+    let resolution = COARSENING.read().unwrap().as_ref().map(|c| c.resolution);
+    assert_eq!(resolution, Some(i64::MAX as i128));
+
+    set_coarsening(None);
+}
@@ -692,3 +692,10 @@ impl IntoInner<time::SystemTime> for SystemTime {
         self.0
     }
 }
+
+#[cfg(target_family = "postgres")]
+impl FromInner<time::Instant> for Instant {
+    fn from_inner(time: time::Instant) -> Instant {
+        Instant(time)
+    }
+}
@@ -0,0 +1,13 @@
+fn main() {
+    println!("cargo:rerun-if-changed=src/pg_try.c");
+    cc::Build::new().file("src/pg_try.c").compile("postpanic_try");
+
+    // The backend stand-in the tests link against, which the library must not.
+    println!("cargo:rerun-if-changed=tests/backend.c");
+    // Its functions take what the real ones do, used or not.
+    cc::Build::new()
+        .file("tests/backend.c")
+        .flag_if_supported("-Wno-unused-parameter")
+        .cargo_metadata(false)
+        .compile("postpanic_backend");
+}
@@ -1,8 +1,346 @@
+//! Carrying errors across the boundary between Rust and Postgres, in both
+//! directions.
+//!
+//! A Rust panic must not unwind into Postgres's C frames, and a Postgres
+//! `ERROR`, which `longjmp`s to the nearest `PG_TRY`, must not jump over Rust
+//! frames, since their destructors would never run. So:
+//!
+//! - [`guard`] wraps every entry point Postgres calls into Rust. A panic that
+//!   reaches it is raised again as an `ereport(ERROR)`, with the panic's
+//!   message and the file and line it started from.
+//! - [`pg_try`] wraps every call from Rust into Postgres. An `ERROR` raised
+//!   in it is caught as `PG_TRY` would, and continues as a Rust unwind with a
+//!   [`PgError`] payload. Once that reaches [`guard`], every Rust frame in
+//!   between has been dropped, and the original error is raised again as if
+//!   Rust had never been involved. The payload must get there: it is not
+//!   safe to catch and keep going.
+//!
+//! `PG_TRY` itself, with the `sigsetjmp` it needs, is in a small C file, so
+//! that no Rust frame ever returns twice.
+//!
+//! This crate links against the backend's own symbols, and only works in a
+//! library loaded into a backend. It assumes Postgres 13 or later.
+
+#![cfg_attr(target_family = "postgres", feature(postgres_ext))]
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Once;
+
+/// Postgres's `ErrorData`, which is only ever handled through a pointer.
+#[repr(C)]
+pub struct ErrorData {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    static mut ErrorContext: *mut c_void;
+    #[cfg(target_family = "postgres")]
+    static InterruptPending: c_int;
+
+    // `PG_TRY` around `f(arg)`, in `pg_try.c`. Returns whether `f` raised an
+    // error.
+    fn postpanic_try(f: extern "C" fn(*mut c_void), arg: *mut c_void) -> bool;
+
+    fn errstart(elevel: c_int, domain: *const c_char) -> bool;
+    fn errfinish(filename: *const c_char, lineno: c_int, funcname: *const c_char);
+    fn errcode(sqlerrcode: c_int) -> c_int;
+    fn errmsg(fmt: *const c_char, ...) -> c_int;
+    #[cfg(target_family = "postgres")]
+    fn errdetail(fmt: *const c_char, ...) -> c_int;
+    fn CopyErrorData() -> *mut ErrorData;
+    fn FlushErrorState();
+    fn FreeErrorData(edata: *mut ErrorData);
+    fn ReThrowError(edata: *mut ErrorData) -> !;
+    fn MemoryContextStrdup(context: *mut c_void, string: *const c_char) -> *mut c_char;
+    #[cfg(target_family = "postgres")]
+    fn ProcessInterrupts();
+}
+
+const ERROR: c_int = 21;
+
+/// Encodes a five-character SQLSTATE, as `MAKE_SQLSTATE` does.
+const fn sqlstate(code: &[u8; 5]) -> c_int {
+    let mut value = 0;
+    let mut i = 0;
+    while i < 5 {
+        value |= ((code[i] - b'0') as c_int & 0x3F) << (6 * i);
+        i += 1;
+    }
+    value
+}
+
+const ERRCODE_INTERNAL_ERROR: c_int = sqlstate(b"XX000");
+#[cfg(target_family = "postgres")]
+const ERRCODE_QUERY_CANCELED: c_int = sqlstate(b"57014");
+#[cfg(target_family = "postgres")]
+const ERRCODE_OUT_OF_MEMORY: c_int = sqlstate(b"53200");
+
+/// A Postgres error caught by [`pg_try`], on its way back to Postgres.
+///
+/// This is the payload of the unwind [`pg_try`] starts. Code that catches
+/// it with [`catch_unwind`](std::panic::catch_unwind) must resume unwinding
+/// with it, so that [`guard`] raises it again, and never drop it instead.
+/// Postgres releases the locks, buffer pins and other resources the failed
+/// call held only when it aborts the (sub)transaction the error is raised
+/// in, so swallowing the error would leave them held.
+pub struct PgError {
+    data: *mut ErrorData,
+}
+
+// It never leaves the backend's one thread, but unwinding needs `Send`.
+unsafe impl Send for PgError {}
+
+impl PgError {
+    /// Returns the caught error, as `CopyErrorData` made it.
+    pub fn as_ptr(&self) -> *mut ErrorData {
+        self.data
+    }
+}
+
+impl Drop for PgError {
+    fn drop(&mut self) {
+        unsafe { FreeErrorData(self.data) }
+    }
+}
+
+impl fmt::Debug for PgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PgError").finish_non_exhaustive()
+    }
+}
+
+/// Calls `f`, which calls into Postgres, and turns an `ERROR` it raises into
+/// a Rust unwind with a [`PgError`] payload.
+///
+/// A panic in `f` continues unwinding once `f` has returned, without
+/// passing through the C frame in between.
+///
+/// # Safety
+///
+/// `f` should do little more than make the call: if the call raises an
+/// `ERROR`, the frames of `f` and of anything it calls are jumped over, so
+/// nothing in them may need dropping. Create such values outside of `f`.
+/// The [`PgError`] must reach [`guard`].
+pub unsafe fn pg_try<T, F: FnOnce() -> T>(f: F) -> T {
+    let mut call: Call<F, T> = (Some(f), None);
+    let caught = postpanic_try(call_once::<F, T>, ptr::addr_of_mut!(call).cast());
+    if caught {
+        panic::resume_unwind(Box::new(catch()));
+    }
+    match call.1 {
+        Some(Ok(result)) => result,
+        Some(Err(payload)) => panic::resume_unwind(payload),
+        None => unreachable!("pg_try returned without calling its function"),
+    }
+}
+
+// The function `pg_try` calls, and what became of the call.
+type Call<F, T> = (Option<F>, Option<std::thread::Result<T>>);
+
+// Called by `postpanic_try` with a `Call`. Unwinding out of here would cross
+// the C frame, so a panic is carried out in the result instead.
+extern "C" fn call_once<F: FnOnce() -> T, T>(call: *mut c_void) {
+    let call = unsafe { &mut *call.cast::<Call<F, T>>() };
+    if let Some(f) = call.0.take() {
+        call.1 = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+    }
+}
+
+/// Takes the error being handled out of Postgres's error state.
+///
+/// Flushing the error state doesn't release what the failed call held, which
+/// is why the [`PgError`] must be raised again.
+unsafe fn catch() -> PgError {
+    let data = CopyErrorData();
+    FlushErrorState();
+    PgError { data }
+}
+
+/// Calls `f`, an entry point called from Postgres, and raises any panic that
+/// escapes it as a Postgres `ERROR`.
+///
+/// A [`PgError`] payload is raised again as it was. On postgres targets:
+///
+/// - [`Interrupted`] lets the pending interrupt be processed, which raises
+///   the error for it. If Postgres has none to act on, it becomes an `ERROR`
+///   with SQLSTATE `XX000` instead.
+/// - [`TimedOut`] becomes the `ERROR` Postgres raises at the statement
+///   timeout, with SQLSTATE `57014`.
+/// - [`OutOfMemory`] becomes the `ERROR` Postgres raises when it runs out of
+///   memory, with SQLSTATE `53200`.
+/// - [`Exit`] becomes an `ERROR` that names its code.
+///
+/// Any other panic becomes an `ERROR` with SQLSTATE `XX000`, the panic's
+/// message, and the file and line the panic started from.
+///
+/// On postgres targets, `f` runs with a panic hook of its own, which is
+/// dropped once it returns. The hook that records where panics start is the
+/// host's hook, set with [`set_host_hook`], which this replaces.
+///
+/// # Safety
+///
+/// This must be called directly from Postgres, with no Rust frames above it
+/// that the error would jump over.
+///
+/// [`Interrupted`]: std::os::postgres::interrupt::Interrupted
+/// [`TimedOut`]: std::os::postgres::time::TimedOut
+/// [`OutOfMemory`]: std::os::postgres::alloc::OutOfMemory
+/// [`Exit`]: std::os::postgres::process::Exit
+/// [`set_host_hook`]: std::os::postgres::panic::set_host_hook
+pub unsafe fn guard<T>(f: impl FnOnce() -> T) -> T {
+    HOOK.call_once(|| install_hook());
+    #[cfg(target_family = "postgres")]
+    let f = || std::os::postgres::panic::with_call_hook(f);
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => raise(payload),
+    }
+}
+
+static HOOK: Once = Once::new();
+
+thread_local! {
+    // Where the latest panic started, for `raise`.
+    static LOCATION: RefCell<Option<(CString, u32)>> = const { RefCell::new(None) };
+}
+
+fn record_location(info: &panic::PanicInfo<'_>) {
+    let location = info.location().map(|l| (c_string(l.file()), l.line()));
+    let _ = LOCATION.try_with(|last| *last.borrow_mut() = location);
+}
+
+/// Records where each panic starts, before running the hook that was set
+/// before.
+#[cfg(not(target_family = "postgres"))]
+unsafe fn install_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        record_location(info);
+        previous(info)
+    }));
+}
+
+/// Records where each panic starts, from the host's hook, which runs within
+/// whatever hooks each call sets.
+#[cfg(target_family = "postgres")]
+unsafe fn install_hook() {
+    std::os::postgres::panic::set_host_hook(Some(Box::new(record_location)));
+}
+
+unsafe fn raise(payload: Box<dyn Any + Send>) -> ! {
+    let payload = match payload.downcast::<PgError>() {
+        Ok(error) => {
+            let error = *error;
+            let data = error.data;
+            std::mem::forget(error);
+            ReThrowError(data)
+        }
+        Err(payload) => payload,
+    };
+    #[cfg(target_family = "postgres")]
+    let payload = {
+        use std::os::postgres::{
+            alloc::OutOfMemory, interrupt::Interrupted, process::Exit, time::TimedOut,
+        };
+
+        if payload.is::<Interrupted>() {
+            drop(payload);
+            if ptr::read_volatile(&InterruptPending) != 0 {
+                ProcessInterrupts();
+            }
+            // Postgres had nothing to act on, so this was no cancel.
+            report(ERRCODE_INTERNAL_ERROR, c_string(&Interrupted.to_string()), None)
+        }
+        if payload.is::<TimedOut>() {
+            drop(payload);
+            report(
+                ERRCODE_QUERY_CANCELED,
+                c_string("canceling statement due to statement timeout"),
+                None,
+            )
+        }
+        let payload = match payload.downcast::<OutOfMemory>() {
+            Ok(oom) => {
+                let detail = c_string(&format!("Failed on request of size {}.", oom.size()));
+                drop(oom);
+                report_out_of_memory(detail)
+            }
+            Err(payload) => payload,
+        };
+        match payload.downcast::<Exit>() {
+            Ok(exit) => {
+                let message = c_string(&exit.to_string());
+                drop(exit);
+                report(ERRCODE_INTERNAL_ERROR, message, None)
+            }
+            Err(payload) => payload,
+        }
+    };
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        c_string(message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        c_string(message)
+    } else {
+        c_string("Box<dyn Any>")
+    };
+    drop(payload);
+    let location = LOCATION.try_with(|last| last.borrow_mut().take()).ok().flatten();
+    report(ERRCODE_INTERNAL_ERROR, message, location)
+}
+
+/// `ereport(ERROR, ...)`, once everything Rust owns has been dropped.
+unsafe fn report(code: c_int, message: CString, location: Option<(CString, u32)>) -> ! {
+    let (file, line) = match location {
+        // `errfinish` keeps the pointer rather than a copy.
+        Some((file, line)) => (MemoryContextStrdup(ErrorContext, file.as_ptr()), line as c_int),
+        None => (ptr::null_mut(), 0),
+    };
+    if errstart(ERROR, ptr::null()) {
+        errcode(code);
+        errmsg(b"%s\0".as_ptr().cast(), message.as_ptr());
+    }
+    drop(message);
+    errfinish(file, line, ptr::null());
+    // `errfinish` does not return from an `ERROR`.
+    std::process::abort()
+}
+
+/// `ereport(ERROR, ...)` for running out of memory, worded as Postgres words
+/// its own.
+#[cfg(target_family = "postgres")]
+unsafe fn report_out_of_memory(detail: CString) -> ! {
+    if errstart(ERROR, ptr::null()) {
+        errcode(ERRCODE_OUT_OF_MEMORY);
+        errmsg(b"out of memory\0".as_ptr().cast());
+        errdetail(b"%s\0".as_ptr().cast(), detail.as_ptr());
+    }
+    drop(detail);
+    errfinish(ptr::null(), 0, ptr::null());
+    std::process::abort()
+}
+
+/// `s` as a C string, with any NUL bytes in it replaced.
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "\u{FFFD}")).unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlstates() {
+        assert_eq!(ERRCODE_INTERNAL_ERROR, 2600);
+        assert_eq!(sqlstate(b"57014"), 5 | 7 << 6 | 1 << 18 | 4 << 24);
+    }
+
     #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn c_strings() {
+        assert_eq!(c_string("a\0b").as_bytes(), "a\u{FFFD}b".as_bytes());
     }
 }
@@ -0,0 +1,43 @@
+/*
+ * PG_TRY for postpanic, in C, so that the frame calling sigsetjmp is one the
+ * compiler knows may return twice.
+ *
+ * This only declares the few backend symbols it uses instead of including
+ * postgres.h, so that it builds without the server headers.
+ */
+
+#include <setjmp.h>
+#include <stdbool.h>
+
+extern sigjmp_buf *PG_exception_stack;
+extern void *error_context_stack;
+extern void *CurrentMemoryContext;
+
+/*
+ * Calls f(arg), and returns whether it raised an ERROR, which is then left
+ * for the caller to take out of the error state. Like PG_TRY, the error
+ * context stack is put back either way. The memory context is put back too,
+ * since PG_CATCH would be entered in ErrorContext.
+ */
+bool
+postpanic_try(void (*f)(void *), void *arg)
+{
+	sigjmp_buf *volatile save_exception_stack = PG_exception_stack;
+	void *volatile save_context_stack = error_context_stack;
+	void *volatile save_memory_context = CurrentMemoryContext;
+	sigjmp_buf	local_sigjmp_buf;
+	volatile bool caught = false;
+
+	if (sigsetjmp(local_sigjmp_buf, 0) == 0)
+	{
+		PG_exception_stack = &local_sigjmp_buf;
+		f(arg);
+	}
+	else
+		caught = true;
+
+	PG_exception_stack = save_exception_stack;
+	error_context_stack = save_context_stack;
+	CurrentMemoryContext = save_memory_context;
+	return caught;
+}
@@ -0,0 +1,175 @@
+/*
+ * Just enough of the backend's error handling to run postpanic outside of
+ * one, for the tests. ereport longjmps to the innermost PG_TRY as the real
+ * one does, and the latest error raised is kept for the tests to look at.
+ */
+
+#include <setjmp.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef struct ErrorData
+{
+	int			sqlerrcode;
+	char	   *message;
+	char	   *filename;
+	int			lineno;
+} ErrorData;
+
+sigjmp_buf *PG_exception_stack = NULL;
+void	   *error_context_stack = NULL;
+void	   *CurrentMemoryContext = NULL;
+void	   *ErrorContext = NULL;
+
+/* The error being raised, or the latest one. */
+static ErrorData error;
+
+static char *
+copy_string(const char *s)
+{
+	return s ? strdup(s) : NULL;
+}
+
+static void
+throw(void)
+{
+	if (PG_exception_stack == NULL)
+		abort();
+	siglongjmp(*PG_exception_stack, 1);
+}
+
+bool
+errstart(int elevel, const char *domain)
+{
+	free(error.message);
+	free(error.filename);
+	memset(&error, 0, sizeof(error));
+	return true;
+}
+
+int
+errcode(int sqlerrcode)
+{
+	error.sqlerrcode = sqlerrcode;
+	return 0;
+}
+
+int
+errmsg(const char *fmt,...)
+{
+	char		buf[1024];
+	va_list		args;
+
+	va_start(args, fmt);
+	vsnprintf(buf, sizeof(buf), fmt, args);
+	va_end(args);
+	free(error.message);
+	error.message = strdup(buf);
+	return 0;
+}
+
+void
+errfinish(const char *filename, int lineno, const char *funcname)
+{
+	error.filename = copy_string(filename);
+	error.lineno = lineno;
+	throw();
+}
+
+ErrorData *
+CopyErrorData(void)
+{
+	ErrorData  *copy = malloc(sizeof(ErrorData));
+
+	copy->sqlerrcode = error.sqlerrcode;
+	copy->message = copy_string(error.message);
+	copy->filename = copy_string(error.filename);
+	copy->lineno = error.lineno;
+	return copy;
+}
+
+void
+FlushErrorState(void)
+{
+}
+
+void
+FreeErrorData(ErrorData *edata)
+{
+	free(edata->message);
+	free(edata->filename);
+	free(edata);
+}
+
+void
+ReThrowError(ErrorData *edata)
+{
+	errstart(21, NULL);
+	error.sqlerrcode = edata->sqlerrcode;
+	error.message = copy_string(edata->message);
+	error.filename = copy_string(edata->filename);
+	error.lineno = edata->lineno;
+	throw();
+}
+
+char *
+MemoryContextStrdup(void *context, const char *string)
+{
+	return strdup(string);
+}
+
+/* ereport(ERROR, (errcode(sqlerrcode), errmsg("%s", message))) */
+void
+backend_ereport(int sqlerrcode, const char *message)
+{
+	errstart(21, NULL);
+	errcode(sqlerrcode);
+	errmsg("%s", message);
+	errfinish(NULL, 0, NULL);
+}
+
+/* PG_TRY around f(arg), as Postgres calls an entry point. */
+bool
+backend_try(void (*f) (void *), void *arg)
+{
+	sigjmp_buf *volatile save_exception_stack = PG_exception_stack;
+	sigjmp_buf	local_sigjmp_buf;
+	volatile bool caught = false;
+
+	if (sigsetjmp(local_sigjmp_buf, 0) == 0)
+	{
+		PG_exception_stack = &local_sigjmp_buf;
+		f(arg);
+	}
+	else
+		caught = true;
+	PG_exception_stack = save_exception_stack;
+	return caught;
+}
+
+int
+backend_error_code(void)
+{
+	return error.sqlerrcode;
+}
+
+const char *
+backend_error_message(void)
+{
+	return error.message ? error.message : "";
+}
+
+const char *
+backend_error_filename(void)
+{
+	return error.filename ? error.filename : "";
+}
+
+int
+backend_error_lineno(void)
+{
+	return error.lineno;
+}
@@ -0,0 +1,83 @@
+//! Errors carried from Postgres through Rust and back, against the stand-in
+//! for the backend's error handling in `backend.c`.
+
+use postpanic::{guard, pg_try};
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::panic;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+#[link(name = "postpanic_backend", kind = "static")]
+extern "C" {
+    fn backend_ereport(sqlerrcode: c_int, message: *const c_char);
+    fn backend_try(f: extern "C" fn(*mut c_void), arg: *mut c_void) -> bool;
+    fn backend_error_code() -> c_int;
+    fn backend_error_message() -> *const c_char;
+    fn backend_error_filename() -> *const c_char;
+    fn backend_error_lineno() -> c_int;
+}
+
+/// `MAKE_SQLSTATE`.
+fn sqlstate(code: &[u8; 5]) -> c_int {
+    code.iter().enumerate().map(|(i, &c)| ((c - b'0') as c_int & 0x3F) << (6 * i)).sum()
+}
+
+fn error_message() -> String {
+    unsafe { CStr::from_ptr(backend_error_message()) }.to_string_lossy().into_owned()
+}
+
+fn error_filename() -> String {
+    unsafe { CStr::from_ptr(backend_error_filename()) }.to_string_lossy().into_owned()
+}
+
+static DROPPED: AtomicBool = AtomicBool::new(false);
+
+struct Guarded;
+
+impl Drop for Guarded {
+    fn drop(&mut self) {
+        DROPPED.store(true, Relaxed);
+    }
+}
+
+// An entry point that calls into Postgres, which raises an `ERROR`.
+extern "C" fn calls_ereport(_: *mut c_void) {
+    unsafe {
+        guard(|| {
+            let _guarded = Guarded;
+            pg_try(|| backend_ereport(sqlstate(b"22012"), b"division by zero\0".as_ptr().cast()))
+        })
+    }
+}
+
+// An entry point that panics.
+extern "C" fn panics(_: *mut c_void) {
+    unsafe { guard(|| panic!("no {} here", "rows")) }
+}
+
+// The backend's error state is global, so a single test makes every trip.
+#[test]
+fn round_trips() {
+    // An `ERROR` raised within `pg_try` unwinds through the Rust frames,
+    // dropping what they hold, and reaches Postgres again as it was.
+    assert!(unsafe { backend_try(calls_ereport, ptr::null_mut()) });
+    assert!(DROPPED.load(Relaxed));
+    assert_eq!(unsafe { backend_error_code() }, sqlstate(b"22012"));
+    assert_eq!(error_message(), "division by zero");
+
+    // A panic becomes an `ERROR` with its message and where it started.
+    assert!(unsafe { backend_try(panics, ptr::null_mut()) });
+    assert_eq!(unsafe { backend_error_code() }, sqlstate(b"XX000"));
+    assert_eq!(error_message(), "no rows here");
+    assert!(error_filename().ends_with("round_trip.rs"));
+    assert!(unsafe { backend_error_lineno() } > 0);
+
+    // A panic within `pg_try` keeps unwinding past it, and leaves Postgres
+    // able to raise errors as before.
+    let payload = panic::catch_unwind(|| unsafe { pg_try(|| panic!("inner")) }).unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"inner"));
+    assert!(unsafe { backend_try(calls_ereport, ptr::null_mut()) });
+
+    // Nothing happens to a call that returns.
+    assert_eq!(unsafe { guard(|| pg_try(|| 42)) }, 42);
+}